@@ -0,0 +1,118 @@
+//! `EncBlock::crc`/`crc2` verification
+//!
+//! Unconfirmed: none of this has been checked against a real protected binary yet.
+//! The guess is plain CRC-32 (ISO-HDLC), `crc` over the raw data of the decrypted
+//! section with its trailing `[0x10; 16]` padding block already zeroed, `crc2` over the
+//! encrypted raw data. [`repack`](crate::repack::repack) writes the same guess, so the
+//! round trip tests can't tell whether it's right.
+//!
+//! Until it is, [`Unpacker::verify_crc`](crate::Unpacker::verify_crc) is off by default.
+//! When a mismatch does show up the other common 32 bit CRCs are tried, a match there
+//! (`CrcMismatch::identified`) means the guess is wrong rather than the key.
+
+use crc::{
+    Algorithm, Crc, CRC_32_BZIP2, CRC_32_ISCSI, CRC_32_ISO_HDLC, CRC_32_JAMCRC, CRC_32_MPEG_2,
+};
+
+/// Algorithm used for verification
+pub const ALGORITHM: &Algorithm<u32> = &CRC_32_ISO_HDLC;
+
+/// Algorithms worth trying when [`ALGORITHM`] doesn't match
+pub const CANDIDATES: [(&str, &Algorithm<u32>); 5] = [
+    ("CRC-32", &CRC_32_ISO_HDLC),
+    ("CRC-32C", &CRC_32_ISCSI),
+    ("CRC-32/BZIP2", &CRC_32_BZIP2),
+    ("CRC-32/MPEG-2", &CRC_32_MPEG_2),
+    ("CRC-32/JAMCRC", &CRC_32_JAMCRC),
+];
+
+pub fn crc32(data: &[u8]) -> u32 {
+    Crc::<u32>::new(ALGORITHM).checksum(data)
+}
+
+/// Name of the first candidate algorithm producing `expected` over `data`
+pub fn identify(data: &[u8], expected: u32) -> Option<&'static str> {
+    CANDIDATES
+        .iter()
+        .find(|(_, algorithm)| Crc::<u32>::new(algorithm).checksum(data) == expected)
+        .map(|(name, _)| *name)
+}
+
+/// Which of the two `EncBlock` checksums didn't match
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CrcKind {
+    /// `crc`, over the decrypted data
+    Plain,
+    /// `crc2`, over the encrypted data
+    Encrypted,
+}
+
+/// Checksum mismatch of one `EncBlock`
+#[derive(Debug, Clone)]
+pub struct CrcMismatch {
+    pub va: u32,
+    pub kind: CrcKind,
+    pub expected: u32,
+    pub actual: u32,
+    /// Another algorithm that does match, if any
+    pub identified: Option<&'static str>,
+}
+
+/// Verifies `data` against `expected`, a zero `expected` means there's nothing to check
+pub fn verify(va: u32, kind: CrcKind, data: &[u8], expected: u32) -> Result<(), CrcMismatch> {
+    let actual = crc32(data);
+    if expected == 0 || actual == expected {
+        Ok(())
+    } else {
+        Err(CrcMismatch {
+            va,
+            kind,
+            expected,
+            actual,
+            identified: identify(data, expected),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHECK: &[u8] = b"123456789";
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(CHECK), 0xCBF4_3926);
+        assert_eq!(crc32(b""), 0);
+    }
+
+    #[test]
+    fn identifies_candidates() {
+        for (name, check) in [
+            ("CRC-32", 0xCBF4_3926),
+            ("CRC-32C", 0xE306_9283),
+            ("CRC-32/BZIP2", 0xFC89_1918),
+            ("CRC-32/MPEG-2", 0x0376_E6E7),
+            ("CRC-32/JAMCRC", 0x340B_C6D9),
+        ] {
+            assert_eq!(identify(CHECK, check), Some(name));
+        }
+        assert_eq!(identify(CHECK, 0x1234_5678), None);
+    }
+
+    #[test]
+    fn verifies_block() {
+        assert!(verify(0x1000, CrcKind::Plain, CHECK, 0xCBF4_3926).is_ok());
+        // Nothing stored, nothing to check
+        assert!(verify(0x1000, CrcKind::Plain, CHECK, 0).is_ok());
+
+        let mismatch = verify(0x2000, CrcKind::Encrypted, CHECK, 0xE306_9283).unwrap_err();
+        assert_eq!(mismatch.va, 0x2000);
+        assert_eq!(mismatch.kind, CrcKind::Encrypted);
+        assert_eq!(mismatch.actual, 0xCBF4_3926);
+        assert_eq!(mismatch.identified, Some("CRC-32C"));
+
+        let mismatch = verify(0x2000, CrcKind::Plain, CHECK, 0x1234_5678).unwrap_err();
+        assert_eq!(mismatch.identified, None);
+    }
+}
@@ -0,0 +1,134 @@
+//! Where to look for licenses besides the EA app's own directories
+//!
+//! The config file is a list of `key = value` lines, `#` starts a comment:
+//!
+//! ```text
+//! # Searched in order, before the EA app's directories
+//! dlf_dir = /mnt/licenses
+//! dlf_dir = ~/licenses
+//! # Every DLF that unpacked something is copied here, as <content id>.dlf
+//! dlf_store = ~/.local/share/rse_ooa_decrypt/dlf
+//! ```
+//!
+//! `RSE_OOA_DLF_DIRS` (a `PATH`-like list) and `RSE_OOA_DLF_STORE` add to it, and
+//! `RSE_OOA_CONFIG` points at a config file other than [`default_path`].
+
+use std::path::{Path, PathBuf};
+
+use crate::error::OoaError;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Config {
+    /// License directories, searched in order
+    pub dlf_dirs: Vec<PathBuf>,
+    /// Where to copy every DLF that was used successfully
+    pub dlf_store: Option<PathBuf>,
+}
+
+/// `%APPDATA%\rse_ooa_decrypt\config` on Windows, `$XDG_CONFIG_HOME/rse_ooa_decrypt/config` elsewhere
+pub fn default_path() -> Option<PathBuf> {
+    #[cfg(target_os = "windows")]
+    let dir = std::env::var_os("APPDATA").map(PathBuf::from);
+    #[cfg(not(target_os = "windows"))]
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")));
+    dir.map(|dir| dir.join("rse_ooa_decrypt").join("config"))
+}
+
+/// Expands a leading `~/`
+fn expand(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => Path::new(&home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+impl Config {
+    pub fn parse(text: &str) -> Result<Config, OoaError> {
+        let mut config = Config::default();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| {
+                    OoaError::BadConfig(format!("line {}: expected key = value", i + 1))
+                })?;
+            match key {
+                "dlf_dir" => config.dlf_dirs.push(expand(value)),
+                "dlf_store" => config.dlf_store = Some(expand(value)),
+                _ => {
+                    return Err(OoaError::BadConfig(format!(
+                        "line {}: unknown setting {}",
+                        i + 1,
+                        key
+                    )))
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    /// Reads the config file and the environment variables
+    ///
+    /// A missing [`default_path`] is fine, a missing `RSE_OOA_CONFIG` isn't.
+    pub fn load() -> Result<Config, OoaError> {
+        let mut config = match std::env::var_os("RSE_OOA_CONFIG") {
+            Some(path) => Config::read(Path::new(&path))?,
+            None => match default_path().filter(|path| path.is_file()) {
+                Some(path) => Config::read(&path)?,
+                None => Config::default(),
+            },
+        };
+        if let Some(dirs) = std::env::var_os("RSE_OOA_DLF_DIRS") {
+            let dirs = std::env::split_paths(&dirs).filter(|dir| !dir.as_os_str().is_empty());
+            config.dlf_dirs.splice(0..0, dirs);
+        }
+        if let Some(store) = std::env::var_os("RSE_OOA_DLF_STORE") {
+            config.dlf_store = Some(PathBuf::from(store));
+        }
+        Ok(config)
+    }
+
+    fn read(path: &Path) -> Result<Config, OoaError> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|err| OoaError::BadConfig(format!("{}: {}", path.display(), err)))?;
+        Config::parse(&text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_config() {
+        let config = Config::parse(
+            "# licenses\n\
+             dlf_dir = /mnt/licenses\n\
+             \n\
+             dlf_dir=relative # from the current directory\n\
+             dlf_store = /var/cache/dlf\n",
+        )
+        .unwrap();
+        assert_eq!(
+            config,
+            Config {
+                dlf_dirs: vec!["/mnt/licenses".into(), "relative".into()],
+                dlf_store: Some("/var/cache/dlf".into()),
+            }
+        );
+        assert!(matches!(
+            Config::parse("dlf_dirs = /mnt"),
+            Err(OoaError::BadConfig(_))
+        ));
+        assert!(matches!(
+            Config::parse("/mnt/licenses"),
+            Err(OoaError::BadConfig(_))
+        ));
+    }
+}
@@ -0,0 +1,69 @@
+//! Recovering the version string behind an `.ooa` version hash
+//!
+//! The hash at `0x2A` is `SHA1` of a `N.NN.NN.NN` version string, a space small
+//! enough (10^7 candidates) to simply brute-force.
+
+use sha1::{Digest, Sha1};
+
+pub fn version_hash(version: &str) -> [u8; 20] {
+    Sha1::digest(version.as_bytes()).into()
+}
+
+/// Searches the whole `N.NN.NN.NN` space, one thread per major version
+pub fn crack(hash: &[u8; 20]) -> Option<String> {
+    std::thread::scope(|scope| {
+        let workers = (0..10u32)
+            .map(|major| scope.spawn(move || crack_major(major, hash)))
+            .collect::<Vec<_>>();
+        workers
+            .into_iter()
+            .filter_map(|worker| worker.join().ok().flatten())
+            .next()
+    })
+}
+
+/// Searches the `<major>.NN.NN.NN` part of the space
+pub fn crack_major(major: u32, hash: &[u8; 20]) -> Option<String> {
+    let mut candidate = *b"0.00.00.00";
+    candidate[0] = b'0' + major as u8;
+    for minor in 0..100 {
+        put_two_digits(&mut candidate[2..4], minor);
+        for patch in 0..100 {
+            put_two_digits(&mut candidate[5..7], patch);
+            for build in 0..100 {
+                put_two_digits(&mut candidate[8..10], build);
+                if Sha1::digest(candidate)[..] == hash[..] {
+                    return String::from_utf8(candidate.to_vec()).ok();
+                }
+            }
+        }
+    }
+    None
+}
+
+fn put_two_digits(buf: &mut [u8], value: u32) {
+    buf[0] = b'0' + (value / 10) as u8;
+    buf[1] = b'0' + (value % 10) as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{HASH_TITANFALL2, LAYOUTS};
+
+    #[test]
+    fn layout_hashes_match_versions() {
+        for layout in &LAYOUTS {
+            assert_eq!(version_hash(layout.version), layout.hash, "{}", layout.name);
+        }
+    }
+
+    #[test]
+    fn cracks_one_major() {
+        assert_eq!(
+            crack_major(5, &HASH_TITANFALL2).as_deref(),
+            Some("5.00.01.35")
+        );
+        assert_eq!(crack_major(4, &HASH_TITANFALL2), None);
+    }
+}
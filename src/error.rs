@@ -0,0 +1,149 @@
+use std::fmt;
+
+use crate::checksum::{CrcKind, CrcMismatch};
+use crate::keycheck::KeyCheck;
+
+/// Everything that can go wrong while unpacking
+#[derive(Debug)]
+pub enum OoaError {
+    /// Reading or writing a file failed
+    Io(std::io::Error),
+    /// `pelite` refused the input
+    Pe(pelite::Error),
+    /// PE file has no sections at all
+    NoSections,
+    /// Data ended before the structure we were reading did
+    Truncated,
+    /// `.ooa` version hash we don't have a parser for
+    UnknownVersion([u8; 20]),
+    /// None of the DLF sources yielded a license
+    DlfNotFound,
+    /// DLF failed to decrypt with either layout
+    BadDlf,
+    /// DLF was found but has no usable `<CipherKey>`
+    MissingCipherKey,
+    /// `EncBlock` points at a VA no section starts at
+    SectionLookup(u32),
+    /// Decrypted section doesn't end in valid PKCS#7 padding, usually a wrong key
+    Padding,
+    /// `EncBlock` checksum doesn't match the section data
+    Checksum(CrcMismatch),
+    /// Decrypted data doesn't look like it was decrypted with the right key
+    WrongKey(KeyCheck),
+    /// Image or `Section` can't be turned back into `.ooa` form
+    Repack(String),
+    /// Decrypted DLF isn't a usable `<License>` document
+    BadLicense(String),
+    /// Config file can't be read or has an unknown setting
+    BadConfig(String),
+    /// CipherKey isn't an AES-128 key, holds its length
+    KeyLength(usize),
+}
+
+impl OoaError {
+    /// Process exit code for the CLI, distinct per failure cause
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            OoaError::Io(_) => 2,
+            OoaError::Pe(_) => 3,
+            OoaError::NoSections => 4,
+            OoaError::Truncated => 5,
+            OoaError::UnknownVersion(_) => 6,
+            OoaError::DlfNotFound => 7,
+            OoaError::BadDlf => 8,
+            OoaError::MissingCipherKey => 9,
+            OoaError::SectionLookup(_) => 10,
+            OoaError::Padding => 11,
+            OoaError::Checksum(_) => 12,
+            OoaError::WrongKey(_) => 13,
+            OoaError::Repack(_) => 14,
+            OoaError::BadLicense(_) => 15,
+            OoaError::BadConfig(_) => 16,
+            OoaError::KeyLength(_) => 17,
+        }
+    }
+}
+
+impl fmt::Display for OoaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OoaError::Io(err) => write!(f, "I/O error: {}", err),
+            OoaError::Pe(err) => write!(f, "Error parsing PE file: {}", err),
+            OoaError::NoSections => write!(f, "Invalid PE File! Zero Sections"),
+            OoaError::Truncated => write!(f, "Invalid .ooa section! Data is truncated"),
+            OoaError::UnknownVersion(hash) => write!(f, "Unknown .ooa version hash! {:X?}", hash),
+            OoaError::DlfNotFound => write!(f, "Can't find correct DLF file!"),
+            OoaError::BadDlf => write!(f, "Failed DLF decrypt!"),
+            OoaError::MissingCipherKey => write!(f, "Failed to get CipherKey from DLF!"),
+            OoaError::SectionLookup(va) => {
+                write!(f, "Failed to find section for decryption! (VA {:08X})", va)
+            }
+            OoaError::Padding => write!(f, "Invalid padding after decryption, wrong key?"),
+            OoaError::Checksum(mismatch) => {
+                write!(
+                    f,
+                    "{} checksum mismatch for section at {:08X}: expected {:08X}, got {:08X}",
+                    match mismatch.kind {
+                        CrcKind::Plain => "Decrypted",
+                        CrcKind::Encrypted => "Encrypted",
+                    },
+                    mismatch.va,
+                    mismatch.expected,
+                    mismatch.actual
+                )?;
+                if let Some(name) = mismatch.identified {
+                    write!(f, " (matches {} though)", name)?;
+                }
+                Ok(())
+            }
+            OoaError::WrongKey(check) => write!(
+                f,
+                "Key does not match this binary (padding {}, entropy {:.2}, OEP prologue {})",
+                if check.padding_ok { "ok" } else { "invalid" },
+                check.entropy,
+                match check.prologue {
+                    Some(true) => "ok",
+                    Some(false) => "not recognised",
+                    None => "not checked",
+                }
+            ),
+            OoaError::Repack(reason) => write!(f, "Can't repack: {}", reason),
+            OoaError::BadLicense(reason) => write!(f, "Invalid DLF license: {}", reason),
+            OoaError::BadConfig(reason) => write!(f, "Invalid config file: {}", reason),
+            OoaError::KeyLength(len) => write!(f, "CipherKey is {} bytes, expected 16", len),
+        }
+    }
+}
+
+impl std::error::Error for OoaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            OoaError::Io(err) => Some(err),
+            OoaError::Pe(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for OoaError {
+    fn from(err: std::io::Error) -> Self {
+        // Every parser reads through a `Cursor`, running out of data means truncation
+        if err.kind() == std::io::ErrorKind::UnexpectedEof {
+            OoaError::Truncated
+        } else {
+            OoaError::Io(err)
+        }
+    }
+}
+
+impl From<pelite::Error> for OoaError {
+    fn from(err: pelite::Error) -> Self {
+        OoaError::Pe(err)
+    }
+}
+
+impl From<block_modes::BlockModeError> for OoaError {
+    fn from(_: block_modes::BlockModeError) -> Self {
+        OoaError::Padding
+    }
+}
@@ -0,0 +1,457 @@
+//! Synthetic protected binaries for tests
+//!
+//! Builds a tiny PE64 (or PE32) with code, imports and relocations, protects it for a given
+//! [`Layout`] through [`repack`] and points the headers at `.ooa` like the real stub
+//! does. [`dlf`] makes the matching license.
+
+use pelite::image::{
+    IMAGE_DIRECTORY_ENTRY_BASERELOC, IMAGE_DIRECTORY_ENTRY_IAT, IMAGE_DIRECTORY_ENTRY_IMPORT,
+    IMAGE_DIRECTORY_ENTRY_TLS, IMAGE_FILE_DLL,
+};
+
+use pelite::PeFile;
+
+use crate::layout::Layout;
+use crate::ooa::{DataDir, EncBlock, Import, Section, Thunk};
+use crate::pe::{PeEditor, SectionHeader};
+use crate::reloc::{RelocBlock, IMAGE_REL_BASED_DIR64, IMAGE_REL_BASED_HIGHLOW};
+use crate::repack::repack;
+use crate::util::{encrypt_dlf, DlfLayout};
+
+pub const KEY: [u8; 16] = *b"fixture-key-0123";
+pub const CONTENT_ID: &str = "Origin.OFR.50.0001234";
+pub const IMAGE_BASE: u64 = 0x1_4000_0000;
+pub const IMAGE_BASE32: u64 = 0x40_0000;
+pub const SIZE_OF_IMAGE: u32 = 0x4000;
+
+pub const TEXT_VA: u32 = 0x1000;
+pub const RDATA_VA: u32 = 0x2000;
+pub const RELOC_VA: u32 = 0x3000;
+
+// From the start of .rdata
+const IMPORT_DIR: u32 = 0;
+const ILT: u32 = 0x40;
+const IAT: u32 = 0x60;
+const HINT_NAME: u32 = 0x80;
+const DLL_NAME: u32 = 0xA0;
+const TLS_DIR: u32 = 0x100;
+const TLS_CALLBACKS: u32 = 0x140;
+
+const FILE_ALIGNMENT: u32 = 0x200;
+const SIZE_OF_HEADERS: u32 = 0x400;
+/// `PointerToRawData` of .text, .rdata and .reloc
+const RAW: [u32; 3] = [0x400, 0x600, 0x800];
+
+fn put(data: &mut [u8], off: usize, bytes: &[u8]) {
+    data[off..off + bytes.len()].copy_from_slice(bytes);
+}
+
+/// Where the sections of the fixture image are
+///
+/// [`Image::DEFAULT`] leaves no room, the others leave a hole for [`Image::protected_at`]
+/// to put `.ooa` in.
+#[derive(Debug, Clone, Copy)]
+pub struct Image {
+    pub text: u32,
+    pub rdata: u32,
+    pub reloc: u32,
+    pub size_of_image: u32,
+    pub pe32: bool,
+    /// Sets `IMAGE_FILE_DLL`
+    pub dll: bool,
+}
+
+impl Image {
+    pub const DEFAULT: Image = Image {
+        text: TEXT_VA,
+        rdata: RDATA_VA,
+        reloc: RELOC_VA,
+        size_of_image: SIZE_OF_IMAGE,
+        pe32: false,
+        dll: false,
+    };
+
+    pub const PE32: Image = Image {
+        pe32: true,
+        ..Image::DEFAULT
+    };
+
+    pub const DLL: Image = Image {
+        dll: true,
+        ..Image::DEFAULT
+    };
+
+    pub fn image_base(&self) -> u64 {
+        if self.pe32 {
+            IMAGE_BASE32
+        } else {
+            IMAGE_BASE
+        }
+    }
+
+    fn relocs(&self) -> Vec<RelocBlock> {
+        let entries = if self.pe32 {
+            // The call's absolute IAT address and the pointer
+            vec![
+                (IMAGE_REL_BASED_HIGHLOW as u16) << 12 | 0x07,
+                (IMAGE_REL_BASED_HIGHLOW as u16) << 12 | 0x10,
+            ]
+        } else {
+            vec![(IMAGE_REL_BASED_DIR64 as u16) << 12 | 0x10, 0]
+        };
+        vec![RelocBlock {
+            page_rva: self.text,
+            entries,
+        }]
+    }
+
+    /// One thunk and the terminator
+    fn iat_size(&self) -> u32 {
+        if self.pe32 {
+            8
+        } else {
+            0x10
+        }
+    }
+
+    /// Pointer sized little endian `value`
+    fn pointer(&self, value: u64) -> Vec<u8> {
+        let width = if self.pe32 { 4 } else { 8 };
+        value.to_le_bytes()[..width].to_vec()
+    }
+
+    fn import(&self) -> Import {
+        Import {
+            characteristics: self.rdata + ILT,
+            timedatestamp: 0,
+            forwarder_chain: 0,
+            name: self.rdata + DLL_NAME,
+            fthunk: self.rdata + IAT,
+        }
+    }
+
+    /// The image as it was before protection
+    pub fn unpacked(&self) -> Vec<u8> {
+        let mut data = vec![0u8; 0xA00];
+        put(&mut data, 0, b"MZ");
+        put(&mut data, 0x3C, &0x40u32.to_le_bytes());
+
+        // (Machine, Characteristics, SizeOfOptionalHeader, Magic)
+        let (machine, mut characteristics, optional_header_size, magic) = if self.pe32 {
+            // EXECUTABLE_IMAGE | 32BIT_MACHINE
+            (0x14Cu16, 0x102u16, 0xE0, 0x10Bu16)
+        } else {
+            // EXECUTABLE_IMAGE | LARGE_ADDRESS_AWARE
+            (0x8664, 0x22, 0xF0, 0x20B)
+        };
+        if self.dll {
+            characteristics |= IMAGE_FILE_DLL;
+        }
+        let nt = 0x40;
+        put(&mut data, nt, b"PE\0\0");
+        put(&mut data, nt + 4, &machine.to_le_bytes());
+        put(&mut data, nt + 6, &3u16.to_le_bytes());
+        put(
+            &mut data,
+            nt + 20,
+            &(optional_header_size as u16).to_le_bytes(),
+        );
+        put(&mut data, nt + 22, &characteristics.to_le_bytes());
+
+        let opt = nt + 24;
+        put(&mut data, opt, &magic.to_le_bytes());
+        put(&mut data, opt + 16, &self.text.to_le_bytes());
+        if self.pe32 {
+            put(&mut data, opt + 28, &(IMAGE_BASE32 as u32).to_le_bytes());
+        } else {
+            put(&mut data, opt + 24, &IMAGE_BASE.to_le_bytes());
+        }
+        put(&mut data, opt + 32, &0x1000u32.to_le_bytes()); // SectionAlignment
+        put(&mut data, opt + 36, &FILE_ALIGNMENT.to_le_bytes());
+        put(&mut data, opt + 40, &6u16.to_le_bytes()); // MajorOperatingSystemVersion
+        put(&mut data, opt + 48, &6u16.to_le_bytes()); // MajorSubsystemVersion
+        put(&mut data, opt + 56, &self.size_of_image.to_le_bytes());
+        put(&mut data, opt + 60, &SIZE_OF_HEADERS.to_le_bytes());
+        put(&mut data, opt + 68, &3u16.to_le_bytes()); // Subsystem: console
+        put(&mut data, opt + 70, &0x8160u16.to_le_bytes()); // DllCharacteristics
+        let rva_and_sizes = if self.pe32 { 92 } else { 108 };
+        put(&mut data, opt + rva_and_sizes, &16u32.to_le_bytes()); // NumberOfRvaAndSizes
+
+        let sections = opt + optional_header_size;
+        let names = [b".text\0\0\0", b".rdata\0\0", b".reloc\0\0"];
+        let vas = [self.text, self.rdata, self.reloc];
+        for i in 0..3 {
+            let header = sections + i * 0x28;
+            put(&mut data, header, &names[i][..]);
+            put(&mut data, header + 8, &0x200u32.to_le_bytes()); // VirtualSize
+            put(&mut data, header + 12, &vas[i].to_le_bytes());
+            put(&mut data, header + 16, &0x200u32.to_le_bytes()); // SizeOfRawData
+            put(&mut data, header + 20, &RAW[i].to_le_bytes());
+            let characteristics: u32 = if i == 0 { 0x6000_0020 } else { 0x4000_0040 };
+            put(&mut data, header + 36, &characteristics.to_le_bytes());
+        }
+
+        let text = RAW[0] as usize;
+        if self.pe32 {
+            // push ebp; mov ebp, esp; push 0; call [IAT]; int3...
+            put(&mut data, text, &[0x55, 0x8B, 0xEC, 0x6A, 0x00, 0xFF, 0x15]);
+            let iat = IMAGE_BASE32 as u32 + self.rdata + IAT;
+            put(&mut data, text + 7, &iat.to_le_bytes());
+            data[text + 11..text + 0x10].fill(0xCC);
+        } else {
+            // sub rsp, 28h; xor ecx, ecx; call [rip+IAT]; int3...
+            put(
+                &mut data,
+                text,
+                &[0x48, 0x83, 0xEC, 0x28, 0x33, 0xC9, 0xFF, 0x15],
+            );
+            let rel = (self.rdata + IAT).wrapping_sub(self.text + 12);
+            put(&mut data, text + 8, &rel.to_le_bytes());
+            data[text + 12..text + 0x10].fill(0xCC);
+        }
+        // ...and a pointer to relocate
+        put(
+            &mut data,
+            text + 0x10,
+            &self.pointer(self.image_base() + self.text as u64),
+        );
+
+        // .rdata: imports of KERNEL32.dll!ExitProcess
+        let rdata = RAW[1] as usize;
+        let hint_name = (self.rdata + HINT_NAME) as u64;
+        put(&mut data, rdata, &self.import().to_bytes());
+        put(&mut data, rdata + ILT as usize, &self.pointer(hint_name));
+        put(&mut data, rdata + IAT as usize, &self.pointer(hint_name));
+        put(&mut data, rdata + HINT_NAME as usize + 2, b"ExitProcess\0");
+        put(&mut data, rdata + DLL_NAME as usize, b"KERNEL32.dll\0");
+
+        // .reloc
+        let relocs = crate::reloc::encode(&self.relocs());
+        put(&mut data, RAW[2] as usize, &relocs);
+
+        let mut pe = PeEditor::new(&mut data).unwrap();
+        pe.set_data_directory(IMAGE_DIRECTORY_ENTRY_IMPORT, self.rdata + IMPORT_DIR, 0x28)
+            .unwrap();
+        pe.set_data_directory(IMAGE_DIRECTORY_ENTRY_IAT, self.rdata + IAT, self.iat_size())
+            .unwrap();
+        pe.set_data_directory(
+            IMAGE_DIRECTORY_ENTRY_BASERELOC,
+            self.reloc,
+            relocs.len() as u32,
+        )
+        .unwrap();
+        data
+    }
+
+    /// What `.ooa` keeps about [`Image::unpacked`]
+    pub fn section(&self) -> Section {
+        let thunk = Thunk {
+            function: self.rdata + HINT_NAME,
+            data_addr: 0,
+        };
+        Section {
+            content_id: CONTENT_ID.to_owned(),
+            oep: self.text as usize,
+            enc_blocks: [self.text, self.rdata]
+                .iter()
+                .map(|&va| EncBlock {
+                    va,
+                    ..Default::default()
+                })
+                .collect(),
+            image_base: self.image_base(),
+            size_of_image: self.size_of_image,
+            import_dir: DataDir {
+                va: self.rdata + IMPORT_DIR,
+                size: 0x28,
+            },
+            iat_dir: DataDir {
+                va: self.rdata + IAT,
+                size: self.iat_size(),
+            },
+            reloc_dir: DataDir {
+                va: self.reloc,
+                size: crate::reloc::encode(&self.relocs()).len() as u32,
+            },
+            imports: vec![self.import()],
+            iat_thunks: vec![thunk.clone()],
+            original_thunks: vec![thunk],
+            relocs: self.relocs(),
+            ..Default::default()
+        }
+    }
+
+    /// [`Image::unpacked`] protected with `layout`, `.ooa` appended like the real thing
+    pub fn protected(&self, layout: &Layout) -> Vec<u8> {
+        self.protect(self.unpacked(), &self.section(), layout)
+    }
+
+    /// Protects `plain`, an [`Image::unpacked`] variant `section` describes
+    pub fn protect(&self, mut plain: Vec<u8>, section: &Section, layout: &Layout) -> Vec<u8> {
+        // The stub fills the IAT itself
+        let iat = (RAW[1] + IAT) as usize;
+        plain[iat..iat + self.pointer(0).len()].fill(0);
+        let mut data = repack(&plain, layout, section, &KEY).unwrap();
+        point_into_ooa(&mut data, self.size_of_image);
+        data
+    }
+
+    /// VAs of the two TLS callbacks of [`Image::unpacked_tls`], both in `.text`
+    pub fn tls_callback_vas(&self) -> [u64; 2] {
+        [
+            self.image_base() + self.text as u64 + 0x20,
+            self.image_base() + self.text as u64 + 0x30,
+        ]
+    }
+
+    /// [`Image::unpacked`] with a TLS directory in `.rdata`
+    pub fn unpacked_tls(&self) -> Vec<u8> {
+        let mut data = self.unpacked();
+        let tls = (RAW[1] + TLS_DIR) as usize;
+        let callbacks = (RAW[1] + TLS_CALLBACKS) as usize;
+        let (directory_size, address_of_callbacks) = if self.pe32 {
+            (0x18, 0x0C)
+        } else {
+            (0x28, 0x18)
+        };
+        let array = self.image_base() + (self.rdata + TLS_CALLBACKS) as u64;
+        put(&mut data, tls + address_of_callbacks, &self.pointer(array));
+        for (i, va) in self.tls_callback_vas().iter().enumerate() {
+            let entry = self.pointer(*va);
+            put(&mut data, callbacks + i * entry.len(), &entry);
+        }
+        PeEditor::new(&mut data)
+            .unwrap()
+            .set_data_directory(
+                IMAGE_DIRECTORY_ENTRY_TLS,
+                self.rdata + TLS_DIR,
+                directory_size,
+            )
+            .unwrap();
+        data
+    }
+
+    /// What `.ooa` keeps about [`Image::unpacked_tls`] with `layout`
+    pub fn section_tls(&self, layout: &Layout) -> Section {
+        let callbacks = self.tls_callback_vas();
+        Section {
+            tls: self.rdata + TLS_DIR,
+            tls_callback: self.rdata + TLS_CALLBACKS,
+            tls_first_callback: callbacks[0],
+            tls_callbacks: if layout.tls_callback_list {
+                callbacks[1..].to_vec()
+            } else {
+                Vec::new()
+            },
+            tls_callback_list: layout.tls_callback_list,
+            ..self.section()
+        }
+    }
+
+    /// [`Image::unpacked_tls`] protected with `layout`, the first callback and TLS pointing at the stub
+    pub fn protected_tls(&self, layout: &Layout) -> Vec<u8> {
+        let mut plain = self.unpacked_tls();
+        let stub = self.image_base() + self.size_of_image as u64 + 0x20;
+        put(
+            &mut plain,
+            (RAW[1] + TLS_CALLBACKS) as usize,
+            &self.pointer(stub),
+        );
+        let mut data = self.protect(plain, &self.section_tls(layout), layout);
+        PeEditor::new(&mut data)
+            .unwrap()
+            .set_data_directory(IMAGE_DIRECTORY_ENTRY_TLS, self.size_of_image + 0x100, 0x28)
+            .unwrap();
+        data
+    }
+
+    /// Like [`Image::protected`], but with `.ooa` at `index` in the section table and at `va`
+    ///
+    /// Raw data follows the order of the section table.
+    pub fn protected_at(&self, layout: &Layout, index: usize, va: u32) -> Vec<u8> {
+        let data = self.protected(layout);
+        let file = PeFile::from_bytes(&data).unwrap();
+        let mut headers = file
+            .section_headers()
+            .iter()
+            .map(|s| {
+                (
+                    SectionHeader {
+                        name: s.Name,
+                        virtual_size: s.VirtualSize,
+                        virtual_address: s.VirtualAddress,
+                        size_of_raw_data: s.SizeOfRawData,
+                        pointer_to_raw_data: s.PointerToRawData,
+                        characteristics: s.Characteristics,
+                    },
+                    s.PointerToRawData as usize..(s.PointerToRawData + s.SizeOfRawData) as usize,
+                )
+            })
+            .collect::<Vec<_>>();
+        let mut ooa = headers.pop().unwrap();
+        assert!(ooa.0.virtual_size <= 0x1000);
+        ooa.0.virtual_address = va;
+        headers.insert(index, ooa);
+
+        let mut new = data[..SIZE_OF_HEADERS as usize].to_vec();
+        for (header, raw) in &mut headers {
+            header.pointer_to_raw_data = new.len() as u32;
+            new.extend_from_slice(&data[raw.clone()]);
+        }
+        let mut pe = PeEditor::new(&mut new).unwrap();
+        for index in (0..headers.len()).rev() {
+            pe.remove_section_header(index).unwrap();
+        }
+        for (header, _) in &headers {
+            pe.append_section_header(header).unwrap();
+        }
+        pe.set_size_of_image(self.size_of_image).unwrap();
+        point_into_ooa(&mut new, va);
+        new
+    }
+}
+
+/// The image as it was before protection
+pub fn unpacked() -> Vec<u8> {
+    Image::DEFAULT.unpacked()
+}
+
+/// What `.ooa` keeps about [`unpacked`]
+pub fn section() -> Section {
+    Image::DEFAULT.section()
+}
+
+/// [`unpacked`] protected with `layout`, headers pointing into `.ooa` like the stub's
+pub fn protected(layout: &Layout) -> Vec<u8> {
+    Image::DEFAULT.protected(layout)
+}
+
+fn point_into_ooa(data: &mut [u8], ooa_va: u32) {
+    let mut pe = PeEditor::new(data).unwrap();
+    pe.set_entry_point(ooa_va + 0x10).unwrap();
+    pe.set_data_directory(IMAGE_DIRECTORY_ENTRY_IMPORT, ooa_va + 0x242, 0x28)
+        .unwrap();
+    pe.set_data_directory(IMAGE_DIRECTORY_ENTRY_IAT, ooa_va + 0x300, 0x10)
+        .unwrap();
+    pe.set_data_directory(IMAGE_DIRECTORY_ENTRY_BASERELOC, 0, 0)
+        .unwrap();
+}
+
+/// Decrypted license for [`CONTENT_ID`] holding [`KEY`]
+pub fn dlf_plain() -> Vec<u8> {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\
+         <License xmlns=\"http://ea.com/license\">\
+         <CipherKey>{}</CipherKey><MachineHash>1</MachineHash><ContentId>{}</ContentId>\
+         <GameToken>token</GameToken><StartTime>2021-01-01T00:00:00Z</StartTime>\
+         <EndTime>2099-12-31T23:59:59Z</EndTime></License>",
+        base64::encode(KEY),
+        CONTENT_ID
+    )
+    .into_bytes()
+}
+
+/// [`dlf_plain`] encrypted with the `0x41` byte header layout
+pub fn dlf() -> Vec<u8> {
+    encrypt_dlf(&dlf_plain(), DlfLayout::Header)
+}
@@ -0,0 +1,235 @@
+//! Best effort parsing of `.ooa` versions that aren't in the [`Registry`](crate::Registry)
+//!
+//! Known layouts differ in very few places, so an unknown build is most likely close
+//! to one of them. Every layout is tried as is, and additionally its head (everything
+//! up to the `EncBlock` array) is combined with a scan for the `unk == 1` marker
+//! followed by the `image_base`/`size_of_image` the PE headers tell us to expect.
+
+use std::io::Cursor;
+
+use crate::layout::{read_tail, Layout};
+use crate::ooa::Section;
+
+/// What the PE headers say about the original image
+#[derive(Debug, Clone)]
+pub struct PeInfo {
+    pub image_base: u64,
+    /// `VirtualAddress` of the `.ooa` section, i.e. the original `SizeOfImage`
+    pub ooa_va: u32,
+    /// `VirtualAddress` of every section
+    pub section_vas: Vec<u32>,
+}
+
+/// How a [`Guess`] was found
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// Known layout parsed the section as is
+    Layout,
+    /// Known layout head plus a scan for the `unk` marker at `offset`
+    Scan { offset: usize },
+}
+
+/// Best candidate for an unknown `.ooa` section
+#[derive(Debug)]
+pub struct Guess {
+    /// Version of the layout the guess is based on
+    pub based_on: &'static str,
+    pub method: Method,
+    /// 0 to 100
+    pub confidence: u8,
+    pub section: Section,
+}
+
+/// Guesses with less confidence than this aren't worth decrypting with
+pub const MIN_CONFIDENCE: u8 = 50;
+
+/// Rates how well `section` fits the PE it came from
+pub fn score(section: &Section, pe: &PeInfo) -> u8 {
+    let mut score = 0;
+    if section.image_base == pe.image_base {
+        score += 30;
+    } else if section.image_base == 0 {
+        // BFV doesn't fill it in
+        score += 10;
+    }
+    if section.size_of_image == pe.ooa_va {
+        score += 30;
+    }
+    if !section.enc_blocks.is_empty()
+        && section
+            .enc_blocks
+            .iter()
+            .all(|b| pe.section_vas.contains(&b.va))
+    {
+        score += 20;
+    }
+    if section.oep != 0 && section.oep < pe.ooa_va as usize {
+        score += 10;
+    }
+    let dirs = [&section.import_dir, &section.reloc_dir, &section.iat_dir];
+    if dirs
+        .iter()
+        .all(|d| d.va as u64 + d.size as u64 <= pe.ooa_va as u64)
+    {
+        score += 10;
+    }
+    score
+}
+
+/// Finds `01 <image_base> <size_of_image>` at or after `from`
+fn scan_marker(data: &[u8], from: usize, pe: &PeInfo) -> Option<usize> {
+    let size = pe.ooa_va.to_le_bytes();
+    let base = pe.image_base.to_le_bytes();
+    let zero = [0u8; 8];
+    (from..data.len().saturating_sub(12)).find(|&i| {
+        data[i] == 1
+            && (data[i + 1..i + 9] == base || data[i + 1..i + 9] == zero)
+            && data[i + 9..i + 13] == size
+    })
+}
+
+/// Tries every layout and returns the most plausible parse, if any
+pub fn guess(data: &[u8], layouts: &[Layout], pe: &PeInfo) -> Option<Guess> {
+    let mut best: Option<Guess> = None;
+    let mut consider = |guess: Guess| {
+        if best
+            .as_ref()
+            .is_none_or(|best| guess.confidence > best.confidence)
+        {
+            best = Some(guess);
+        }
+    };
+    for layout in layouts {
+        if let Ok((section, unk)) = layout.parse_raw(data) {
+            let confidence = score(&section, pe);
+            consider(Guess {
+                based_on: layout.version,
+                method: Method::Layout,
+                confidence: if unk == 1 {
+                    confidence
+                } else {
+                    confidence.saturating_sub(10)
+                },
+                section,
+            });
+        }
+
+        let mut cursor = Cursor::new(data);
+        if let Ok(mut section) = layout.parse_head(&mut cursor) {
+            if let Some(offset) = scan_marker(data, cursor.position() as usize, pe) {
+                cursor.set_position(offset as u64 + 1);
+                if read_tail(&mut cursor, &mut section).is_ok() {
+                    consider(Guess {
+                        based_on: layout.version,
+                        method: Method::Scan { offset },
+                        // Finding the marker doesn't prove the head is right
+                        confidence: score(&section, pe).saturating_sub(5),
+                        section,
+                    });
+                }
+            }
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::{UnkAt, LAYOUTS};
+    use crate::{fixture, unpack, DlfSource, OoaError, Unpacker};
+
+    fn pe_info() -> PeInfo {
+        PeInfo {
+            image_base: fixture::IMAGE_BASE,
+            ooa_va: fixture::SIZE_OF_IMAGE,
+            section_vas: vec![
+                fixture::TEXT_VA,
+                fixture::RDATA_VA,
+                fixture::RELOC_VA,
+                fixture::SIZE_OF_IMAGE,
+            ],
+        }
+    }
+
+    /// `fixture::protected(layout)` with a version hash nobody knows
+    fn unknown_hash(layout: &Layout) -> Vec<u8> {
+        let mut data = fixture::protected(layout);
+        let pos = data.windows(20).position(|w| w == layout.hash).unwrap();
+        data[pos..pos + 20].fill(0xAA);
+        data
+    }
+
+    #[test]
+    fn unpacks_unknown_hash() {
+        for layout in &LAYOUTS {
+            let unpacked = unpack(
+                &unknown_hash(layout),
+                &[DlfSource::CipherKey(fixture::KEY.to_vec())],
+            )
+            .unwrap();
+            assert!(
+                unpacked.confidence.unwrap() >= MIN_CONFIDENCE,
+                "{}",
+                layout.name
+            );
+            assert!(unpacked
+                .notes
+                .iter()
+                .any(|note| note.starts_with("Unknown .ooa version hash")));
+            assert_eq!(unpacked.data, fixture::unpacked(), "{}", layout.name);
+        }
+    }
+
+    #[test]
+    fn heuristic_can_be_turned_off() {
+        let err = Unpacker {
+            heuristic: false,
+            ..Default::default()
+        }
+        .unpack(
+            &unknown_hash(&LAYOUTS[0]),
+            &[DlfSource::CipherKey(fixture::KEY.to_vec())],
+        )
+        .unwrap_err();
+        assert!(
+            matches!(err, OoaError::UnknownVersion([0xAA, ..])),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn scans_for_moved_marker() {
+        // Like Titanfall 2, but with the marker further away than any known layout has it
+        let layout = Layout {
+            unk_at: UnkAt::Skip(500),
+            ..LAYOUTS[0].clone()
+        };
+        let section = fixture::section();
+        let data = layout.serialize(&section).unwrap();
+        let guess = guess(&data, &LAYOUTS, &pe_info()).unwrap();
+        assert!(
+            matches!(guess.method, Method::Scan { .. }),
+            "{:?}",
+            guess.method
+        );
+        assert!(guess.confidence >= MIN_CONFIDENCE);
+        assert_eq!(guess.section.image_base, fixture::IMAGE_BASE);
+        assert_eq!(guess.section.size_of_image, fixture::SIZE_OF_IMAGE);
+        assert_eq!(guess.section.reloc_dir.va, section.reloc_dir.va);
+    }
+
+    #[test]
+    fn low_confidence_for_other_image() {
+        let data = LAYOUTS[1].serialize(&fixture::section()).unwrap();
+        let mut pe = pe_info();
+        assert_eq!(score(&LAYOUTS[1].parse(&data).unwrap(), &pe), 100);
+        // Same .ooa in an image it doesn't belong to
+        pe.image_base = 0x1_8000_0000;
+        pe.ooa_va = 0x9000;
+        pe.section_vas = vec![0x1000, 0x9000];
+        let guess = guess(&data, &LAYOUTS, &pe).unwrap();
+        assert!(guess.confidence < MIN_CONFIDENCE, "{}", guess.confidence);
+    }
+}
@@ -0,0 +1,159 @@
+//! Resolving the import descriptors kept in `.ooa` to DLL and function names
+//!
+//! Names live in the protected image itself (usually `.rdata`), so this has to run
+//! on the decrypted output.
+
+use pelite::PeFile;
+
+use crate::error::OoaError;
+use crate::ooa::{Import, Section, Thunk};
+use crate::util::{is_pe32, patch_rva, pointer_size};
+
+const IMAGE_ORDINAL_FLAG32: u32 = 1 << 31;
+const IMAGE_ORDINAL_FLAG64: u64 = 1 << 63;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportedFunction {
+    ByName { hint: u16, name: String },
+    ByOrdinal(u16),
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedImport {
+    pub dll: String,
+    pub functions: Vec<ImportedFunction>,
+}
+
+/// Resolves `imports` against the (decrypted) PE file in `image`
+pub fn resolve(image: &[u8], imports: &[Import]) -> Result<Vec<ResolvedImport>, OoaError> {
+    let file = PeFile::from_bytes(image)?;
+    imports
+        .iter()
+        .map(|import| {
+            let dll = file.derva_c_str(import.name)?.to_string();
+            // Bound imports have addresses in the IAT, prefer the lookup table
+            let thunks_rva = if import.characteristics != 0 {
+                import.characteristics
+            } else {
+                import.fthunk
+            };
+            // (by ordinal, thunk)
+            let thunks = if is_pe32(file) {
+                file.derva_slice_s::<u32>(thunks_rva, 0)?
+                    .iter()
+                    .map(|&thunk| (thunk & IMAGE_ORDINAL_FLAG32 != 0, thunk as u64))
+                    .collect::<Vec<_>>()
+            } else {
+                file.derva_slice_s::<u64>(thunks_rva, 0)?
+                    .iter()
+                    .map(|&thunk| (thunk & IMAGE_ORDINAL_FLAG64 != 0, thunk))
+                    .collect()
+            };
+            let functions = thunks
+                .into_iter()
+                .map(|(by_ordinal, thunk)| {
+                    if by_ordinal {
+                        Ok(ImportedFunction::ByOrdinal(thunk as u16))
+                    } else {
+                        let rva = thunk as u32;
+                        Ok(ImportedFunction::ByName {
+                            hint: *file.derva::<u16>(rva)?,
+                            name: file.derva_c_str(rva.saturating_add(2))?.to_string(),
+                        })
+                    }
+                })
+                .collect::<Result<Vec<_>, OoaError>>()?;
+            Ok(ResolvedImport { dll, functions })
+        })
+        .collect()
+}
+
+/// Thunks plus the terminator, `width` bytes each
+fn thunks_to_bytes(thunks: &[Thunk], width: usize) -> Vec<u8> {
+    thunks
+        .iter()
+        .map(Thunk::value)
+        .chain(std::iter::once(0))
+        .flat_map(|value| value.to_le_bytes().into_iter().take(width))
+        .collect()
+}
+
+/// Writes the import descriptors and thunks from `.ooa` back into the image
+///
+/// `file` is the original PE, `new` the unpacked one with the same section layout.
+/// Descriptors go to `import_dir`, thunks to the tables of the first descriptor,
+/// which is the only one `.ooa` keeps thunks for. Returns what was done.
+pub fn restore(file: PeFile, new: &mut [u8], section: &Section) -> Vec<String> {
+    let mut notes = Vec::new();
+    if section.imports.is_empty() {
+        return notes;
+    }
+    let mut report = |what: &str, result: Result<bool, OoaError>| match result {
+        Ok(true) => notes.push(format!("Restored {}", what)),
+        Ok(false) => {}
+        Err(err) => notes.push(format!("Failed to restore {}: {}", what, err)),
+    };
+
+    let descriptors = section
+        .imports
+        .iter()
+        .map(Import::to_bytes)
+        .chain(std::iter::once([0u8; 20]))
+        .flatten()
+        .collect::<Vec<_>>();
+    if section.import_dir.va == 0 || (section.import_dir.size as usize) < descriptors.len() {
+        report("import descriptors", Err(OoaError::Truncated));
+    } else {
+        report(
+            "import descriptors",
+            patch_rva(file, new, section.import_dir.va, &descriptors),
+        );
+    }
+
+    let first = &section.imports[0];
+    let width = pointer_size(file);
+    if !section.iat_thunks.is_empty() {
+        report(
+            "IAT thunks",
+            patch_rva(
+                file,
+                new,
+                first.fthunk,
+                &thunks_to_bytes(&section.iat_thunks, width),
+            ),
+        );
+    }
+    if !section.original_thunks.is_empty() && first.characteristics != 0 {
+        report(
+            "original thunks",
+            patch_rva(
+                file,
+                new,
+                first.characteristics,
+                &thunks_to_bytes(&section.original_thunks, width),
+            ),
+        );
+    }
+    notes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Image;
+
+    #[test]
+    fn resolves_pe32() {
+        let image = Image::PE32;
+        let imports = resolve(&image.unpacked(), &image.section().imports).unwrap();
+        assert_eq!(imports.len(), 1);
+        assert_eq!(imports[0].dll, "KERNEL32.dll");
+        assert_eq!(
+            imports[0].functions,
+            [ImportedFunction::ByName {
+                hint: 0,
+                name: "ExitProcess".to_owned()
+            }]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Telling whether a CipherKey actually belongs to the binary
+//!
+//! AES-CBC happily "decrypts" with any key, so the result is judged instead:
+//! every section must end in valid PKCS#7 padding, the plaintext must not look
+//! random and the OEP should start with something resembling an x64 (or x86 for
+//! PE32) prologue.
+
+/// Entropy (bits per byte) above which decrypted data is considered still encrypted
+pub const MAX_ENTROPY: f64 = 7.5;
+
+/// Common MSVC x64 function starts
+const PROLOGUES: [&[u8]; 14] = [
+    &[0x48, 0x83, 0xEC],       // sub rsp, imm8
+    &[0x48, 0x81, 0xEC],       // sub rsp, imm32
+    &[0x48, 0x89, 0x5C, 0x24], // mov [rsp+x], rbx
+    &[0x48, 0x89, 0x4C, 0x24], // mov [rsp+x], rcx
+    &[0x48, 0x89, 0x54, 0x24], // mov [rsp+x], rdx
+    &[0x4C, 0x89, 0x44, 0x24], // mov [rsp+x], r8
+    &[0x48, 0x8B, 0xC4],       // mov rax, rsp
+    &[0x4C, 0x8B, 0xDC],       // mov r11, rsp
+    &[0x40, 0x53],             // push rbx
+    &[0x40, 0x55],             // push rbp
+    &[0x40, 0x57],             // push rdi
+    &[0x55, 0x48, 0x8B, 0xEC], // push rbp; mov rbp, rsp
+    &[0xE9],                   // jmp
+    &[0xE8],                   // call
+];
+
+/// Common MSVC x86 function starts
+const PROLOGUES32: [&[u8]; 9] = [
+    &[0x55, 0x8B, 0xEC], // push ebp; mov ebp, esp
+    &[0x8B, 0xFF, 0x55], // mov edi, edi; push ebp
+    &[0x83, 0xEC],       // sub esp, imm8
+    &[0x81, 0xEC],       // sub esp, imm32
+    &[0x6A],             // push imm8 (__SEH_prolog)
+    &[0x68],             // push imm32
+    &[0x53, 0x56],       // push ebx; push esi
+    &[0xE9],             // jmp
+    &[0xE8],             // call
+];
+
+/// Outcome of the key validation
+#[derive(Debug, Clone)]
+pub struct KeyCheck {
+    /// Every decrypted section ends in valid PKCS#7 padding
+    pub padding_ok: bool,
+    /// Entropy of all decrypted data
+    pub entropy: f64,
+    /// Whether the OEP looks like a function start, `None` if it isn't in decrypted data
+    pub prologue: Option<bool>,
+}
+
+impl KeyCheck {
+    pub fn matches(&self) -> bool {
+        self.padding_ok && self.entropy <= MAX_ENTROPY
+    }
+}
+
+/// Checks the last block of a decrypted section
+pub fn valid_pkcs7(last_block: &[u8]) -> bool {
+    match last_block.last() {
+        Some(&n) if (1..=16).contains(&n) && n as usize <= last_block.len() => last_block
+            [last_block.len() - n as usize..]
+            .iter()
+            .all(|&b| b == n),
+        _ => false,
+    }
+}
+
+/// Shannon entropy in bits per byte
+pub fn entropy<'a>(chunks: impl IntoIterator<Item = &'a [u8]>) -> f64 {
+    let mut counts = [0u64; 256];
+    let mut total = 0u64;
+    for chunk in chunks {
+        for &b in chunk {
+            counts[b as usize] += 1;
+        }
+        total += chunk.len() as u64;
+    }
+    if total == 0 {
+        return 0.0;
+    }
+    counts
+        .iter()
+        .filter(|&&c| c != 0)
+        .map(|&c| {
+            let p = c as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+pub fn looks_like_prologue(code: &[u8]) -> bool {
+    PROLOGUES.iter().any(|p| code.starts_with(p))
+}
+
+pub fn looks_like_prologue32(code: &[u8]) -> bool {
+    PROLOGUES32.iter().any(|p| code.starts_with(p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_pkcs7() {
+        let mut block = [0x41u8; 16];
+        block[12..].fill(4);
+        assert!(valid_pkcs7(&block));
+        assert!(valid_pkcs7(&[0x10; 16]));
+        block[15] = 0;
+        assert!(!valid_pkcs7(&block));
+        block[15] = 17;
+        assert!(!valid_pkcs7(&block));
+        // Claims 4 bytes, only 3 are padding
+        block[12..].copy_from_slice(&[4, 3, 4, 4]);
+        assert!(!valid_pkcs7(&block));
+        // Longer than what's there
+        assert!(!valid_pkcs7(&[2]));
+        assert!(!valid_pkcs7(&[]));
+    }
+
+    #[test]
+    fn measures_entropy() {
+        assert_eq!(entropy([]), 0.0);
+        assert_eq!(entropy([&[0xCCu8; 64][..]]), 0.0);
+        // Split across chunks, counted together
+        assert_eq!(entropy([&[0u8; 32][..], &[1u8; 32][..]]), 1.0);
+        let every_byte = (0..=255).collect::<Vec<u8>>();
+        assert_eq!(entropy([&every_byte[..]]), 8.0);
+        assert!(entropy([&every_byte[..]]) > MAX_ENTROPY);
+    }
+}
@@ -0,0 +1,417 @@
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+
+use crate::error::OoaError;
+use crate::ooa::{
+    read_content_id, read_data_dir, read_enc_block, read_import, read_thunk, write_content_id,
+    write_data_dir, write_enc_block, write_thunk, Section,
+};
+use crate::reloc;
+use crate::version::VersionParser;
+
+// All of these are checked against `crack::version_hash` in its tests
+
+/// Corresponds to `SHA1(b"5.00.01.35")`
+pub const HASH_TITANFALL2: [u8; 20] = [
+    144, 186, 35, 2, 239, 178, 23, 166, 193, 39, 171, 1, 174, 248, 38, 101, 194, 51, 160, 24,
+];
+
+/// Corresponds to `SHA1(b"5.02.04.66")`
+pub const HASH_APEX_S11_1: [u8; 20] = [
+    250, 245, 169, 146, 9, 235, 14, 14, 206, 72, 175, 215, 8, 35, 218, 185, 77, 140, 48, 61,
+];
+
+/// Corresponds to `SHA1(b"5.02.08.75")`
+pub const HASH_SKATE_CPT: [u8; 20] = [
+    0x4B, 0x4B, 0x61, 0xB5, 0xE1, 0x2E, 0xB6, 0xEB, 0xD6, 0x85, 0x31, 0xCC, 0xB9, 0x73, 0x14, 0xFB,
+    0xF7, 0x4D, 0xF5, 0x5A,
+];
+
+/// Corresponds to `SHA1(b"5.02.15.92")`
+pub const HASH_BF2042: [u8; 20] = [
+    214, 24, 5, 34, 117, 81, 199, 112, 69, 244, 93, 188, 146, 127, 63, 245, 249, 98, 11, 217,
+];
+
+/// Where `oep` sits relative to the end of the TLS data
+#[derive(Debug, Clone, Copy)]
+pub enum OepAt {
+    /// Right after it
+    Next,
+    /// At the next position `p` with `p % align == offset % align`
+    Aligned { offset: i64, align: i64 },
+}
+
+/// How far the `unk` marker is from the end of the `EncBlock` array
+#[derive(Debug, Clone, Copy)]
+pub enum UnkAt {
+    /// Fixed amount of bytes
+    Skip(i64),
+    /// `slots` fixed size `EncBlock` slots of which only `enc_blocks_count` are used, then `extra` bytes
+    Slots { slots: i64, extra: i64 },
+}
+
+/// Declarative description of one `.ooa` build
+///
+/// Every known version shares the same overall structure, this captures the bits that move around.
+#[derive(Debug, Clone)]
+pub struct Layout {
+    pub hash: [u8; 20],
+    pub version: &'static str,
+    pub name: &'static str,
+    /// Zero terminated list of TLS callback VAs follows the first callback
+    pub tls_callback_list: bool,
+    pub oep_at: OepAt,
+    pub unk_at: UnkAt,
+    /// `unk` is known to always be 1
+    pub check_unk: bool,
+}
+
+/// Size of a serialized `EncBlock`
+pub const ENC_BLOCK_SIZE: i64 = 0x30;
+
+pub const LAYOUTS: [Layout; 4] = [
+    Layout {
+        hash: HASH_TITANFALL2,
+        version: "5.00.01.35",
+        name: "Titanfall 2",
+        tls_callback_list: false,
+        oep_at: OepAt::Next,
+        unk_at: UnkAt::Skip(393),
+        check_unk: true,
+    },
+    Layout {
+        hash: HASH_APEX_S11_1,
+        version: "5.02.04.66",
+        name: "Retail Apex, BFV",
+        tls_callback_list: false,
+        oep_at: OepAt::Next,
+        unk_at: UnkAt::Skip(392),
+        check_unk: true,
+    },
+    Layout {
+        hash: HASH_SKATE_CPT,
+        version: "5.02.08.75",
+        name: "Skate CPT",
+        tls_callback_list: false,
+        oep_at: OepAt::Next,
+        unk_at: UnkAt::Skip(0xF0 + 8),
+        check_unk: false,
+    },
+    Layout {
+        hash: HASH_BF2042,
+        version: "5.02.15.92",
+        name: "BF2042 aka Kingston",
+        tls_callback_list: true,
+        oep_at: OepAt::Aligned {
+            offset: 0x1ea,
+            align: 0x100,
+        },
+        unk_at: UnkAt::Slots {
+            slots: 10,
+            extra: 8,
+        },
+        check_unk: true,
+    },
+];
+
+impl Layout {
+    pub fn parse(&self, data: &[u8]) -> Result<Section, OoaError> {
+        let (mut section, unk) = self.parse_raw(data)?;
+        if self.check_unk && unk != 1 {
+            section.notes.push(format!(
+                "Unexpected unk marker {} (always 1 in {})",
+                unk, self.version
+            ));
+        }
+        Ok(section)
+    }
+
+    /// Same as [`Layout::parse`], but hands back the `unk` marker instead of checking it
+    pub fn parse_raw(&self, data: &[u8]) -> Result<(Section, u8), OoaError> {
+        let mut cursor = Cursor::new(data);
+        let mut section = self.parse_head(&mut cursor)?;
+        cursor.seek(SeekFrom::Current(match self.unk_at {
+            UnkAt::Skip(skip) => skip,
+            UnkAt::Slots { slots, extra } => {
+                ENC_BLOCK_SIZE * (slots - section.enc_blocks.len() as i64) + extra
+            }
+        }))?;
+        let unk = cursor.read_u8()?;
+        read_tail(&mut cursor, &mut section)?;
+        Ok((section, unk))
+    }
+
+    /// Parses everything up to and including the `EncBlock` array
+    ///
+    /// Leaves `cursor` right after the last `EncBlock`.
+    pub fn parse_head(&self, cursor: &mut Cursor<&[u8]>) -> Result<Section, OoaError> {
+        let content_id = read_content_id(cursor.get_ref())?;
+        cursor.seek(SeekFrom::Start(0x242))?;
+        let mut imports = Vec::new();
+        loop {
+            let import = read_import(cursor)?;
+            if import.characteristics == 0 {
+                break;
+            }
+            imports.push(import);
+        }
+        let mut iat_thunks = Vec::new();
+        loop {
+            let iat = read_thunk(cursor)?;
+            if iat.function == 0 {
+                break;
+            }
+            iat_thunks.push(iat);
+        }
+        let mut original_thunks = Vec::new();
+        loop {
+            let original = read_thunk(cursor)?;
+            if original.function == 0 {
+                break;
+            }
+            original_thunks.push(original);
+        }
+        cursor.seek(SeekFrom::Current(72))?;
+        let reloc_max_size = cursor.read_u32::<LE>()?;
+        let _reloc_new_size = cursor.read_u32::<LE>()?;
+        if reloc_max_size as u64 > (cursor.get_ref().len() as u64).saturating_sub(cursor.position())
+        {
+            return Err(OoaError::Truncated);
+        }
+        let mut reloc_data = vec![0u8; reloc_max_size as usize];
+        cursor.read_exact(&mut reloc_data)?;
+        let relocs = reloc::decode(&reloc_data);
+        let tls = cursor.read_u32::<LE>()?;
+        let tls_callback = cursor.read_u32::<LE>()?;
+        let tls_first_callback = cursor.read_u64::<LE>()?;
+        let mut tls_callbacks = Vec::new();
+        if self.tls_callback_list {
+            loop {
+                let tls_callback_va = cursor.read_u64::<LE>()?;
+                if tls_callback_va == 0 {
+                    break;
+                }
+                tls_callbacks.push(tls_callback_va);
+            }
+        }
+
+        if let OepAt::Aligned { offset, align } = self.oep_at {
+            cursor.seek(SeekFrom::Current(
+                (offset - cursor.position() as i64).rem_euclid(align),
+            ))?;
+        }
+
+        let oep = cursor.read_u32::<LE>()?;
+        let enc_blocks_count = cursor.read_u8()?;
+        let enc_blocks = (0..enc_blocks_count)
+            .map(|_| read_enc_block(cursor))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Section {
+            content_id,
+            oep: oep as usize,
+            enc_blocks,
+            imports,
+            iat_thunks,
+            original_thunks,
+            tls,
+            tls_callback,
+            tls_first_callback,
+            tls_callbacks,
+            tls_callback_list: self.tls_callback_list,
+            relocs,
+            ..Default::default()
+        })
+    }
+
+    /// Inverse of [`Layout::parse`]
+    ///
+    /// Bytes the parser skips are left zero and `unk` is written as 1.
+    pub fn serialize(&self, section: &Section) -> Result<Vec<u8>, OoaError> {
+        let mut head = vec![0u8; 0x242];
+        head[0x2A..0x3E].copy_from_slice(&self.hash);
+        write_content_id(&mut head, &section.content_id)?;
+        let mut cursor = Cursor::new(head);
+        cursor.seek(SeekFrom::End(0))?;
+
+        for import in &section.imports {
+            if import.characteristics == 0 {
+                return Err(OoaError::Repack(
+                    "import without OriginalFirstThunk".to_owned(),
+                ));
+            }
+            cursor.write_all(&import.to_bytes())?;
+        }
+        cursor.write_all(&[0u8; 20])?;
+        for thunk in section.iat_thunks.iter().chain(&section.original_thunks) {
+            if thunk.function == 0 {
+                return Err(OoaError::Repack("thunk with a zero low half".to_owned()));
+            }
+        }
+        for thunks in [&section.iat_thunks, &section.original_thunks] {
+            for thunk in thunks {
+                write_thunk(&mut cursor, thunk)?;
+            }
+            cursor.write_u64::<LE>(0)?;
+        }
+        cursor.write_all(&[0u8; 72])?;
+        let reloc_data = reloc::encode(&section.relocs);
+        cursor.write_u32::<LE>(reloc_data.len() as u32)?; // reloc_max_size
+        cursor.write_u32::<LE>(reloc_data.len() as u32)?; // reloc_new_size
+        cursor.write_all(&reloc_data)?;
+        cursor.write_u32::<LE>(section.tls)?;
+        cursor.write_u32::<LE>(section.tls_callback)?;
+        cursor.write_u64::<LE>(section.tls_first_callback)?;
+        if self.tls_callback_list {
+            for &va in &section.tls_callbacks {
+                cursor.write_u64::<LE>(va)?;
+            }
+            cursor.write_u64::<LE>(0)?;
+        } else if !section.tls_callbacks.is_empty() {
+            return Err(OoaError::Repack(format!(
+                "{} doesn't keep a TLS callback list",
+                self.version
+            )));
+        }
+
+        if let OepAt::Aligned { offset, align } = self.oep_at {
+            let pad = (offset - cursor.position() as i64).rem_euclid(align);
+            cursor.write_all(&vec![0u8; pad as usize])?;
+        }
+
+        cursor.write_u32::<LE>(section.oep as u32)?;
+        let max_enc_blocks = match self.unk_at {
+            UnkAt::Slots { slots, .. } => slots,
+            UnkAt::Skip(_) => u8::MAX as i64,
+        };
+        if section.enc_blocks.len() as i64 > max_enc_blocks {
+            return Err(OoaError::Repack(format!(
+                "{} encrypted blocks don't fit",
+                section.enc_blocks.len()
+            )));
+        }
+        cursor.write_u8(section.enc_blocks.len() as u8)?;
+        for block in &section.enc_blocks {
+            write_enc_block(&mut cursor, block)?;
+        }
+        let skip = match self.unk_at {
+            UnkAt::Skip(skip) => skip,
+            UnkAt::Slots { slots, extra } => {
+                ENC_BLOCK_SIZE * (slots - section.enc_blocks.len() as i64) + extra
+            }
+        };
+        cursor.write_all(&vec![0u8; skip as usize])?;
+        cursor.write_u8(1)?; // unk
+
+        cursor.write_u64::<LE>(section.image_base)?;
+        cursor.write_u32::<LE>(section.size_of_image)?;
+        write_data_dir(&mut cursor, &section.import_dir)?;
+        write_data_dir(&mut cursor, &section.reloc_dir)?;
+        write_data_dir(&mut cursor, &section.iat_dir)?;
+        Ok(cursor.into_inner())
+    }
+}
+
+/// Reads `image_base`, `size_of_image` and the data directories following the `unk` marker
+pub fn read_tail(cursor: &mut Cursor<&[u8]>, section: &mut Section) -> Result<(), OoaError> {
+    section.image_base = cursor.read_u64::<LE>()?;
+    section.size_of_image = cursor.read_u32::<LE>()?;
+    section.import_dir = read_data_dir(cursor)?;
+    section.reloc_dir = read_data_dir(cursor)?;
+    section.iat_dir = read_data_dir(cursor)?;
+    Ok(())
+}
+
+impl VersionParser for Layout {
+    fn hash(&self) -> [u8; 20] {
+        self.hash
+    }
+
+    fn version(&self) -> &str {
+        self.version
+    }
+
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn parse(&self, data: &[u8]) -> Result<Section, OoaError> {
+        Layout::parse(self, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+
+    #[test]
+    fn serialize_round_trips() {
+        for layout in &LAYOUTS {
+            let mut section = fixture::section();
+            section.tls_callback_list = layout.tls_callback_list;
+            if layout.tls_callback_list {
+                section.tls = 0x2100;
+                section.tls_callback = 0x2128;
+                section.tls_first_callback = fixture::IMAGE_BASE + 0x1100;
+                section.tls_callbacks = vec![fixture::IMAGE_BASE + 0x1180];
+            }
+            let data = layout.serialize(&section).unwrap();
+            let (parsed, unk) = layout.parse_raw(&data).unwrap();
+            assert_eq!(unk, 1);
+            assert_eq!(
+                format!("{:?}", parsed),
+                format!("{:?}", section),
+                "{}",
+                layout.name
+            );
+        }
+    }
+
+    #[test]
+    fn aligned_oep_before_align() {
+        // offset < align, and smaller than where the TLS data ends
+        let layout = Layout {
+            oep_at: OepAt::Aligned {
+                offset: 0x10,
+                align: 0x100,
+            },
+            ..LAYOUTS[3].clone()
+        };
+        let mut section = fixture::section();
+        section.tls_callback_list = true;
+        let data = layout.serialize(&section).unwrap();
+        let (parsed, unk) = layout.parse_raw(&data).unwrap();
+        assert_eq!(unk, 1);
+        assert_eq!(format!("{:?}", parsed), format!("{:?}", section));
+        let oep = (0x10..data.len())
+            .step_by(0x100)
+            .find(|&pos| data[pos..pos + 4] == (section.oep as u32).to_le_bytes());
+        assert!(oep.is_some());
+    }
+
+    #[test]
+    fn notes_unexpected_unk() {
+        let layout = &LAYOUTS[0];
+        let mut data = layout.serialize(&fixture::section()).unwrap();
+        // unk sits right before image_base, size_of_image and the three data directories
+        let unk = data.len() - 8 - 4 - 3 * 8 - 1;
+        assert_eq!(data[unk], 1);
+        data[unk] = 2;
+        let section = layout.parse(&data).unwrap();
+        assert_eq!(
+            section.notes,
+            ["Unexpected unk marker 2 (always 1 in 5.00.01.35)"]
+        );
+    }
+
+    #[test]
+    fn truncated_section() {
+        let data = LAYOUTS[1].serialize(&fixture::section()).unwrap();
+        assert!(matches!(
+            LAYOUTS[1].parse(&data[..data.len() - 4]),
+            Err(OoaError::Truncated)
+        ));
+    }
+}
@@ -0,0 +1,1035 @@
+//! Respawn's Source Engine `.ooa` decrypt library
+//!
+//! Parses the `.ooa` section appended by Origin/EA app DRM, decrypts the
+//! protected sections with the CipherKey from the matching `.dlf` license
+//! and restores the PE headers. See [`unpack`].
+
+use std::ops::Range;
+use std::path::PathBuf;
+
+use pelite::image::{
+    IMAGE_DIRECTORY_ENTRY_BASERELOC, IMAGE_DIRECTORY_ENTRY_IAT, IMAGE_DIRECTORY_ENTRY_IMPORT,
+    IMAGE_DIRECTORY_ENTRY_SECURITY, IMAGE_FILE_DLL, IMAGE_SECTION_HEADER,
+};
+use pelite::PeFile;
+
+use crate::checksum::{CrcKind, CrcMismatch};
+use crate::heuristic::PeInfo;
+use crate::imports::ResolvedImport;
+use crate::keycheck::KeyCheck;
+use crate::layout::LAYOUTS;
+use crate::license::License;
+use crate::ooa::Section;
+use crate::overlay::OverlayMode;
+use crate::pe::PeEditor;
+use crate::util::{
+    aes_decrypt_inplace_raw, decrypt_dlf, dlf_get_cipher, find_dlf_in, get_dlf_auto, image_base,
+    is_pe32, pe_checksum, size_of_image, store_dlf,
+};
+
+pub mod checksum;
+pub mod config;
+pub mod crack;
+pub mod error;
+#[cfg(test)]
+mod fixture;
+pub mod heuristic;
+pub mod imports;
+pub mod keycheck;
+pub mod layout;
+pub mod license;
+pub mod ooa;
+pub mod overlay;
+pub mod pe;
+pub mod reloc;
+pub mod repack;
+pub mod tls;
+pub mod util;
+pub mod version;
+
+pub use error::OoaError;
+pub use version::{Registry, VersionParser};
+
+/// Where to get the license (and with it the CipherKey) from
+#[derive(Debug, Clone)]
+pub enum DlfSource {
+    /// Look for `<content_id>.dlf` in the usual places, see [`get_dlf_auto`]
+    Auto,
+    /// Look for `<content_id>.dlf` in a directory, see [`find_dlf_in`]
+    Dir(PathBuf),
+    /// Path to an encrypted `.dlf` file
+    File(PathBuf),
+    /// Contents of an encrypted `.dlf` file
+    Encrypted(Vec<u8>),
+    /// Raw CipherKey, skips the DLF altogether
+    CipherKey(Vec<u8>),
+}
+
+/// Result of [`unpack`]
+#[derive(Debug)]
+pub struct UnpackedImage {
+    /// Human readable `.ooa` version
+    pub version: String,
+    /// Set when the version was unknown and the section was parsed heuristically
+    pub confidence: Option<u8>,
+    /// Parsed `.ooa` section
+    pub section: Section,
+    /// Decrypted DLF, if the key came from one
+    pub dlf: Option<Vec<u8>>,
+    /// Parsed `dlf`
+    pub license: Option<License>,
+    /// Where `dlf` was read from, if it came from a file
+    pub dlf_path: Option<PathBuf>,
+    /// CipherKey used for decryption
+    pub key: Vec<u8>,
+    /// Unpacked PE file
+    pub data: Vec<u8>,
+    /// Whether the image is a DLL rather than an executable
+    pub is_dll: bool,
+    /// `EncBlock` checksums that didn't match, empty unless [`Unpacker::verify_crc`] is set
+    pub crc_mismatches: Vec<CrcMismatch>,
+    /// Things worth telling the user about that didn't stop unpacking
+    pub notes: Vec<String>,
+}
+
+impl UnpackedImage {
+    /// DLL and function names of the original imports kept in `.ooa`
+    pub fn imports(&self) -> Result<Vec<ResolvedImport>, OoaError> {
+        imports::resolve(&self.data, &self.section.imports)
+    }
+}
+
+pub fn get_ooa_hash(data: &[u8]) -> Option<[u8; 20]> {
+    if data.len() < 0x3E {
+        None
+    } else {
+        let mut hash = [0u8; 20];
+        hash.copy_from_slice(&data[0x2A..0x3E]);
+        Some(hash)
+    }
+}
+
+/// Finds the `.ooa` section, returning its index, header and raw data
+///
+/// Looks it up by name, then by a version hash `is_known` accepts wherever the
+/// section is, and falls back to the last section.
+fn find_ooa<'a>(
+    file: PeFile<'a>,
+    pe_bytes: &'a [u8],
+    is_known: impl Fn(&[u8; 20]) -> bool,
+) -> Result<(usize, &'a IMAGE_SECTION_HEADER, &'a [u8]), OoaError> {
+    let headers = file.section_headers().as_slice();
+    let raw_data = |s: &IMAGE_SECTION_HEADER| {
+        pe_bytes.get(
+            s.PointerToRawData as usize..s.PointerToRawData as usize + s.SizeOfRawData as usize,
+        )
+    };
+    let index = headers
+        .iter()
+        .position(|s| s.name().ok() == Some(".ooa"))
+        .or_else(|| {
+            headers.iter().position(|s| {
+                raw_data(s)
+                    .and_then(get_ooa_hash)
+                    .is_some_and(|hash| is_known(&hash))
+            })
+        })
+        .or_else(|| headers.len().checked_sub(1))
+        .ok_or(OoaError::NoSections)?;
+    let section_header = &headers[index];
+    let ooa = raw_data(section_header).ok_or(OoaError::Truncated)?;
+    Ok((index, section_header, ooa))
+}
+
+/// Lists what in the unpacked image still points into `.ooa`
+///
+/// Removing the section is only safe when this comes back empty.
+fn ooa_references(
+    pe: &PeEditor,
+    section: &Section,
+    image_base: u64,
+    ooa: Range<u32>,
+) -> Result<Vec<String>, OoaError> {
+    let mut references = Vec::new();
+    if ooa.contains(&(section.oep as u32)) {
+        references.push(format!("OEP {:08X}", section.oep));
+    }
+    for index in 0..pe.number_of_data_directories()? {
+        // The only one holding a file offset
+        if index == IMAGE_DIRECTORY_ENTRY_SECURITY {
+            continue;
+        }
+        let (va, size) = pe.data_directory(index)?;
+        if size != 0 && va < ooa.end && va.saturating_add(size) > ooa.start {
+            references.push(format!("data directory {} ({:08X})", index, va));
+        }
+    }
+    for va in tls::callbacks(section) {
+        let rva = va.wrapping_sub(image_base);
+        if rva <= u32::MAX as u64 && ooa.contains(&(rva as u32)) {
+            references.push(format!("TLS callback {:016X}", va));
+        }
+    }
+    Ok(references)
+}
+
+/// Reads the `.ooa` version hash of a protected PE file
+pub fn read_ooa_hash(pe_bytes: &[u8]) -> Result<[u8; 20], OoaError> {
+    let file = PeFile::from_bytes(pe_bytes)?;
+    let (_, _, ooa) = find_ooa(file, pe_bytes, |_| false)?;
+    get_ooa_hash(ooa).ok_or(OoaError::Truncated)
+}
+
+/// Decrypted DLF, its license, where it was read from and the CipherKey
+type ResolvedKey = (Option<Vec<u8>>, Option<License>, Option<PathBuf>, Vec<u8>);
+
+/// Gets the key from the first source that works
+///
+/// DLFs that aren't a well formed `<License>` fall back to [`dlf_get_cipher`], which
+/// is noted in `notes`. So are files in directories that don't decrypt, the search
+/// goes on past them.
+fn resolve_key(
+    content_id: &str,
+    dlf_sources: &[DlfSource],
+    notes: &mut Vec<String>,
+) -> Result<ResolvedKey, OoaError> {
+    for source in dlf_sources {
+        let (dlf, path) = match source {
+            DlfSource::Auto => match get_dlf_auto(content_id, notes) {
+                Some((path, dlf)) => (Some(dlf), Some(path)),
+                None => (None, None),
+            },
+            DlfSource::Dir(dir) => match find_dlf_in(dir, content_id, notes) {
+                Some((path, dlf)) => (Some(dlf), Some(path)),
+                None => (None, None),
+            },
+            DlfSource::File(path) => match std::fs::read(path) {
+                Ok(data) => (Some(decrypt_dlf(&data)?), Some(path.clone())),
+                Err(_) => (None, None),
+            },
+            DlfSource::Encrypted(data) => (Some(decrypt_dlf(data)?), None),
+            DlfSource::CipherKey(key) => return Ok((None, None, None, key.clone())),
+        };
+        if let Some(dlf) = dlf {
+            return match License::parse(&dlf) {
+                Ok(license) => {
+                    let key = license.cipher_key.clone();
+                    Ok((Some(dlf), Some(license), path, key))
+                }
+                Err(err) => match dlf_get_cipher(&dlf) {
+                    Some(key) => {
+                        notes.push(format!("{}, found the CipherKey anyway", err));
+                        Ok((Some(dlf), None, path, key))
+                    }
+                    None => Err(err),
+                },
+            };
+        }
+    }
+    Err(OoaError::DlfNotFound)
+}
+
+/// Unpacks an `.ooa` protected PE file with the built-in parsers
+///
+/// `dlf_sources` are tried in order until one of them yields a license.
+pub fn unpack(pe_bytes: &[u8], dlf_sources: &[DlfSource]) -> Result<UnpackedImage, OoaError> {
+    Unpacker::default().unpack(pe_bytes, dlf_sources)
+}
+
+/// Unpacking configuration
+pub struct Unpacker {
+    /// `.ooa` versions to recognise
+    pub registry: Registry,
+    /// Guess the layout of unknown versions instead of failing, see [`heuristic`]
+    pub heuristic: bool,
+    /// Verify `EncBlock` checksums and report mismatches
+    ///
+    /// Off by default, the algorithm isn't confirmed on a real binary yet, see [`checksum`].
+    pub verify_crc: bool,
+    /// Fail on `EncBlock` checksum mismatches instead of just reporting them, implies `verify_crc`
+    pub strict_crc: bool,
+    /// Keep going even if the key doesn't seem to match the binary, see [`keycheck`]
+    pub force: bool,
+    /// What to do with data past the last section
+    pub overlay: OverlayMode,
+    /// Zero the (no longer valid) Authenticode `SECURITY` directory entry
+    pub clear_security: bool,
+    /// Recompute the optional header `CheckSum`
+    pub fix_checksum: bool,
+    /// Write the import descriptors and thunks kept in `.ooa` back where they differ
+    pub restore_imports: bool,
+    /// Write the TLS directory and callbacks kept in `.ooa` back
+    pub restore_tls: bool,
+    /// Write the base relocations kept in `.ooa` back where they differ
+    pub restore_relocs: bool,
+    /// Save the DLF here once it unpacked the image, see [`store_dlf`]
+    ///
+    /// Only written to, add a [`DlfSource::Dir`] for it to be searched as well.
+    pub dlf_store: Option<PathBuf>,
+}
+
+impl Default for Unpacker {
+    fn default() -> Self {
+        Unpacker {
+            registry: Registry::default(),
+            heuristic: true,
+            verify_crc: false,
+            strict_crc: false,
+            force: false,
+            overlay: OverlayMode::Relocate,
+            clear_security: false,
+            fix_checksum: false,
+            restore_imports: true,
+            restore_tls: true,
+            restore_relocs: true,
+            dlf_store: None,
+        }
+    }
+}
+
+impl Unpacker {
+    /// See [`unpack`]
+    pub fn unpack(
+        &self,
+        pe_bytes: &[u8],
+        dlf_sources: &[DlfSource],
+    ) -> Result<UnpackedImage, OoaError> {
+        let file = PeFile::from_bytes(pe_bytes)?;
+        let mut notes = Vec::new();
+        let sections_num = file.section_headers().iter().len();
+        let (ooa_index, section_header, ooa) =
+            find_ooa(file, pe_bytes, |hash| self.registry.by_hash(hash).is_some())?;
+        let (version, confidence, mut section) = match self.registry.parse(ooa) {
+            Ok((parser, section)) => (parser.version().to_owned(), None, section),
+            Err(OoaError::UnknownVersion(hash)) if self.heuristic => {
+                let pe = PeInfo {
+                    image_base: image_base(file),
+                    ooa_va: section_header.VirtualAddress,
+                    section_vas: file
+                        .section_headers()
+                        .iter()
+                        .map(|s| s.VirtualAddress)
+                        .collect(),
+                };
+                let guess = heuristic::guess(ooa, &LAYOUTS, &pe)
+                    .filter(|g| g.confidence >= heuristic::MIN_CONFIDENCE)
+                    .ok_or(OoaError::UnknownVersion(hash))?;
+                notes.push(format!(
+                    "Unknown .ooa version hash {:X?}, parsed like {} ({:?}) with {}% confidence",
+                    hash, guess.based_on, guess.method, guess.confidence
+                ));
+                (
+                    format!("unknown (like {})", guess.based_on),
+                    Some(guess.confidence),
+                    guess.section,
+                )
+            }
+            Err(err) => return Err(err),
+        };
+        notes.append(&mut section.notes);
+        // BFV doesn't match here
+        if section.image_base != 0 && confidence.is_none() && image_base(file) != section.image_base
+        {
+            notes.push(format!(
+                "ImageBase {:X} doesn't match the one in .ooa ({:X})",
+                image_base(file),
+                section.image_base
+            ));
+        }
+        // .ooa starts where the original image ended
+        if confidence.is_none()
+            && ooa_index == sections_num - 1
+            && section.size_of_image != 0
+            && section_header.VirtualAddress != section.size_of_image
+        {
+            notes.push(format!(
+                ".ooa starts at {:08X}, not where the original image ended ({:08X})",
+                section_header.VirtualAddress, section.size_of_image
+            ));
+        }
+
+        let (dlf, license, dlf_path, key) =
+            resolve_key(&section.content_id, dlf_sources, &mut notes)?;
+        if let Some(path) = &dlf_path {
+            notes.push(format!("Using DLF {}", path.display()));
+        }
+        if let Some(license) = license
+            .as_ref()
+            .filter(|l| l.content_id != section.content_id)
+        {
+            notes.push(format!(
+                "DLF is for {}, .ooa wants {}",
+                license.content_id, section.content_id
+            ));
+        }
+
+        // .ooa is only dropped at the end, once it's known nothing points into it anymore
+        let mut new = pe_bytes.to_vec();
+        let is_dll = file.file_header().Characteristics & IMAGE_FILE_DLL != 0;
+
+        // Decrypt every section...
+        let verify_crc = self.verify_crc || self.strict_crc;
+        let mut crc_mismatches = Vec::new();
+        let mut padding_ok = true;
+        let mut decrypted = Vec::new();
+        for block in &section.enc_blocks {
+            let section = file
+                .section_headers()
+                .as_slice()
+                .iter()
+                .find(|s| s.VirtualAddress == block.va)
+                .ok_or(OoaError::SectionLookup(block.va))?;
+            let start = section.PointerToRawData as usize;
+            let end = section
+                .PointerToRawData
+                .saturating_add(section.SizeOfRawData) as usize;
+            if start < 0x10 || end < start + 0x10 || end > new.len() {
+                return Err(OoaError::Truncated);
+            }
+            let mut iv = [0u8; 16];
+            iv[..].copy_from_slice(&new[start - 0x10..start]);
+            if verify_crc {
+                if let Err(mismatch) =
+                    checksum::verify(block.va, CrcKind::Encrypted, &new[start..end], block.crc2)
+                {
+                    crc_mismatches.push(mismatch);
+                }
+            }
+            aes_decrypt_inplace_raw(&key, &iv, &mut new[start..end])?;
+            padding_ok &= keycheck::valid_pkcs7(&new[end - 0x10..end]);
+            // fix padding of one block
+            if new[end - 0x10..end] == [0x10u8; 16] {
+                new[end - 0x10..end].copy_from_slice(&[0u8; 16]);
+            }
+            if verify_crc {
+                if let Err(mismatch) =
+                    checksum::verify(block.va, CrcKind::Plain, &new[start..end], block.crc)
+                {
+                    crc_mismatches.push(mismatch);
+                }
+            }
+            decrypted.push(start..end);
+        }
+
+        let oep_code = file
+            .section_headers()
+            .iter()
+            .find(|s| {
+                s.VirtualAddress as usize <= section.oep
+                    && section.oep < s.VirtualAddress.saturating_add(s.SizeOfRawData) as usize
+            })
+            .map(|s| s.PointerToRawData as usize + section.oep - s.VirtualAddress as usize)
+            .filter(|&off| decrypted.iter().any(|range| range.contains(&off)))
+            .and_then(|off| new.get(off..off + 4));
+        let key_check = KeyCheck {
+            padding_ok,
+            entropy: keycheck::entropy(decrypted.iter().map(|range| &new[range.clone()])),
+            prologue: oep_code.map(if is_pe32(file) {
+                keycheck::looks_like_prologue32
+            } else {
+                keycheck::looks_like_prologue
+            }),
+        };
+        let key_ok = key_check.matches();
+        if !key_ok {
+            if !self.force {
+                return Err(OoaError::WrongKey(key_check));
+            }
+            notes.push(format!(
+                "{}, continuing anyway",
+                OoaError::WrongKey(key_check)
+            ));
+        } else if key_check.prologue == Some(false) {
+            notes.push("OEP doesn't look like a function prologue".to_owned());
+        }
+
+        for mismatch in &crc_mismatches {
+            if self.strict_crc {
+                return Err(OoaError::Checksum(mismatch.clone()));
+            }
+            notes.push(OoaError::Checksum(mismatch.clone()).to_string());
+        }
+
+        let ooa_range = section_header.VirtualAddress
+            ..section_header
+                .VirtualAddress
+                .saturating_add(section_header.VirtualSize);
+
+        // fix OEP
+        PeEditor::new(&mut new)?.set_entry_point(section.oep as u32)?;
+        if is_dll {
+            notes.push(if section.oep != 0 {
+                format!("Restored DllMain entry point: {:08X}", section.oep)
+            } else {
+                "DLL has no entry point".to_owned()
+            });
+        }
+
+        // fix import, reloc and iat directories, whichever .ooa kept
+        let mut pe = PeEditor::new(&mut new)?;
+        for (name, index, dir) in [
+            (
+                "ImportDir",
+                IMAGE_DIRECTORY_ENTRY_IMPORT,
+                &section.import_dir,
+            ),
+            (
+                "RelocDir",
+                IMAGE_DIRECTORY_ENTRY_BASERELOC,
+                &section.reloc_dir,
+            ),
+            ("IATDir", IMAGE_DIRECTORY_ENTRY_IAT, &section.iat_dir),
+        ] {
+            if dir.va != 0 && dir.size != 0 {
+                pe.set_data_directory(index, dir.va, dir.size)?;
+                notes.push(format!(
+                    "{}: restored {:08X} ({:#X} bytes)",
+                    name, dir.va, dir.size
+                ));
+                continue;
+            }
+            let (va, size) = pe.data_directory(index)?;
+            let explanation = if va == 0 || size == 0 {
+                "header entry is empty too".to_owned()
+            } else if ooa_range.contains(&va) {
+                format!("header entry {:08X} points into .ooa", va)
+            } else {
+                format!("kept header entry {:08X} ({:#X} bytes)", va, size)
+            };
+            notes.push(format!("{}: not kept in .ooa, {}", name, explanation));
+        }
+
+        if self.restore_imports {
+            notes.extend(imports::restore(file, &mut new, &section));
+        }
+
+        if self.restore_relocs {
+            let size_of_image = size_of_image(file);
+            notes.extend(reloc::restore(file, &mut new, &section, size_of_image));
+        }
+        if is_dll {
+            // DLLs get rebased whenever their preferred base is taken
+            let (va, size) =
+                PeEditor::new(&mut new)?.data_directory(IMAGE_DIRECTORY_ENTRY_BASERELOC)?;
+            if va == 0 || size == 0 {
+                notes.push(format!(
+                    "DLL has no relocations, it only loads at its preferred base {:X}",
+                    image_base(file)
+                ));
+            } else if !self.restore_relocs && !section.relocs.is_empty() {
+                notes.push(
+                    "DLL relocations weren't restored from .ooa, rebasing may break it".to_owned(),
+                );
+            }
+        }
+
+        if self.restore_tls {
+            notes.extend(tls::restore(file, &mut new, &section, ooa_range.clone()));
+        }
+
+        let references = ooa_references(
+            &PeEditor::new(&mut new)?,
+            &section,
+            image_base(file),
+            ooa_range,
+        )?;
+        if references.is_empty() && ooa_index == 0 && sections_num > 1 {
+            notes.push(
+                "Keeping .ooa, it's the first section and removing it would leave a hole in the image"
+                    .to_owned(),
+            );
+        } else if references.is_empty() {
+            let raw_start = section_header.PointerToRawData as usize;
+            let raw_size = section_header.SizeOfRawData as usize;
+            let raw_end = file
+                .section_headers()
+                .iter()
+                .map(|s| s.PointerToRawData.saturating_add(s.SizeOfRawData) as usize)
+                .max()
+                .unwrap_or(raw_start + raw_size);
+
+            if raw_start + raw_size > raw_end.min(new.len()) {
+                return Err(OoaError::Truncated);
+            }
+            // Drop .ooa raw data, the overlay is put back below
+            new.truncate(raw_end);
+            new.drain(raw_start..raw_start + raw_size);
+
+            let mut pe = PeEditor::new(&mut new)?;
+            pe.remove_section_header(ooa_index)?;
+
+            // Raw data after .ooa moved back
+            for index in 0..pe.number_of_sections()? {
+                let pointer = pe.section_pointer_to_raw_data(index)?;
+                if pointer as usize > raw_start {
+                    pe.set_section_pointer_to_raw_data(
+                        index,
+                        pointer.saturating_sub(raw_size as u32),
+                    )?;
+                }
+            }
+
+            if ooa_index == sections_num - 1 {
+                // fix size of image
+                if section.size_of_image != 0 {
+                    pe.set_size_of_image(section.size_of_image)?;
+                } else {
+                    // manual?
+                    let new_val = size_of_image(file).saturating_sub(0x1000);
+                    notes.push(format!("Manual fixing of SizeOfImage: {:08X}", new_val));
+                    pe.set_size_of_image(new_val)?;
+                }
+            } else {
+                // Sections have to be contiguous, let the previous one cover the hole
+                let headers = file.section_headers().as_slice();
+                let (prev, next) = (&headers[ooa_index - 1], &headers[ooa_index + 1]);
+                let prev_name = prev.name().unwrap_or("previous section");
+                match next.VirtualAddress.checked_sub(prev.VirtualAddress) {
+                    Some(virtual_size) => {
+                        pe.set_section_virtual_size(ooa_index - 1, virtual_size)?;
+                        notes.push(format!(
+                            "Removed .ooa from the middle of the section table, {} now spans {:#X} bytes",
+                            prev_name, virtual_size
+                        ));
+                    }
+                    None => notes.push(format!(
+                        "Removed .ooa from the middle of the section table, sections aren't in address order so {} was left as is",
+                        prev_name
+                    )),
+                }
+            }
+        } else {
+            notes.push(format!(
+                "Keeping .ooa and SizeOfImage, still referenced by: {}",
+                references.join(", ")
+            ));
+        }
+
+        if let Some(overlay) = overlay::find(file, pe_bytes.len()) {
+            notes.push(overlay::apply(self.overlay, &overlay, pe_bytes, &mut new)?);
+        }
+
+        if self.clear_security
+            && PeEditor::new(&mut new)?.set_data_directory(IMAGE_DIRECTORY_ENTRY_SECURITY, 0, 0)?
+        {
+            // Signature can't be valid after unpacking anyway
+            notes.push("Cleared SECURITY directory".to_owned());
+        }
+
+        if self.fix_checksum {
+            let checksum_off = PeEditor::new(&mut new)?.checksum_offset();
+            let checksum = pe_checksum(&new, checksum_off);
+            PeEditor::new(&mut new)?.set_checksum(checksum)?;
+            notes.push(format!("Recomputed CheckSum: {:08X}", checksum));
+        }
+
+        // Only licenses that are known good, and not the ones already in there
+        let store = self.dlf_store.as_ref().filter(|store| {
+            key_ok && dlf_path.as_ref().and_then(|path| path.parent()) != Some(store.as_path())
+        });
+        if let (Some(store), Some(dlf)) = (store, &dlf) {
+            notes.push(match store_dlf(store, &section.content_id, dlf) {
+                Ok(path) => format!("Stored DLF as {}", path.display()),
+                Err(err) => format!("Failed to store DLF in {}: {}", store.display(), err),
+            });
+        }
+
+        Ok(UnpackedImage {
+            version,
+            confidence,
+            section,
+            dlf,
+            license,
+            dlf_path,
+            key,
+            data: new,
+            is_dll,
+            crc_mismatches,
+            notes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+    use crate::imports::ImportedFunction;
+    use crate::util::{encrypt_dlf, DlfLayout};
+
+    #[test]
+    fn unpacks_every_layout() {
+        for layout in &LAYOUTS {
+            // Only proves repack and unpack agree on the checksums
+            let unpacked = Unpacker {
+                verify_crc: true,
+                ..Default::default()
+            }
+            .unpack(
+                &fixture::protected(layout),
+                &[DlfSource::Encrypted(fixture::dlf())],
+            )
+            .unwrap();
+            assert_eq!(unpacked.version, layout.version);
+            assert!(unpacked.crc_mismatches.is_empty(), "{}", layout.name);
+            assert_eq!(unpacked.data, fixture::unpacked(), "{}", layout.name);
+        }
+    }
+
+    #[test]
+    fn unpacks_pe32() {
+        let image = fixture::Image::PE32;
+        for layout in &LAYOUTS {
+            let unpacked = unpack(
+                &image.protected(layout),
+                &[DlfSource::CipherKey(fixture::KEY.to_vec())],
+            )
+            .unwrap();
+            // Written back as 4 byte thunks, the terminator included
+            assert!(unpacked.notes.contains(&"Restored IAT thunks".to_owned()));
+            assert_eq!(unpacked.data, image.unpacked(), "{}", layout.name);
+        }
+    }
+
+    #[test]
+    fn unpacks_dll() {
+        let image = fixture::Image::DLL;
+        let unpacked = unpack(
+            &image.protected(&LAYOUTS[1]),
+            &[DlfSource::CipherKey(fixture::KEY.to_vec())],
+        )
+        .unwrap();
+        assert!(unpacked.is_dll);
+        assert!(unpacked
+            .notes
+            .contains(&"Restored DllMain entry point: 00001000".to_owned()));
+        assert!(!unpacked
+            .notes
+            .iter()
+            .any(|note| note.starts_with("DLL has no relocations")));
+        assert_eq!(unpacked.data, image.unpacked());
+    }
+
+    #[test]
+    fn notes_dll_without_relocations() {
+        let image = fixture::Image::DLL;
+        let section = Section {
+            reloc_dir: Default::default(),
+            ..image.section()
+        };
+        let protected = image.protect(image.unpacked(), &section, &LAYOUTS[1]);
+        let unpacked = unpack(&protected, &[DlfSource::CipherKey(fixture::KEY.to_vec())]).unwrap();
+        assert!(unpacked.is_dll);
+        assert!(unpacked.notes.contains(
+            &"DLL has no relocations, it only loads at its preferred base 140000000".to_owned()
+        ));
+    }
+
+    #[test]
+    fn reads_hash_of_every_layout() {
+        for layout in &LAYOUTS {
+            assert_eq!(
+                read_ooa_hash(&fixture::protected(layout)).unwrap(),
+                layout.hash
+            );
+        }
+    }
+
+    #[test]
+    fn removes_ooa_from_the_middle() {
+        let image = fixture::Image {
+            reloc: 0x4000,
+            size_of_image: 0x5000,
+            ..fixture::Image::DEFAULT
+        };
+        let protected = image.protected_at(&LAYOUTS[1], 2, 0x3000);
+        let file = PeFile::from_bytes(&protected).unwrap();
+        assert_eq!(file.section_headers().as_slice()[2].name(), Ok(".ooa"));
+        let ooa_raw = file.section_headers().as_slice()[2].SizeOfRawData;
+
+        let unpacked = unpack(&protected, &[DlfSource::CipherKey(fixture::KEY.to_vec())]).unwrap();
+        let file = PeFile::from_bytes(&unpacked.data).unwrap();
+        let sections = file.section_headers().as_slice();
+        assert_eq!(sections.len(), 3);
+        assert_eq!(sections[1].name(), Ok(".rdata"));
+        assert_eq!(sections[1].VirtualSize, 0x2000);
+        assert_eq!(sections[2].name(), Ok(".reloc"));
+        assert_eq!(sections[2].PointerToRawData, 0x800);
+        assert_eq!(size_of_image(file), 0x5000);
+        assert_eq!(unpacked.data.len(), protected.len() - ooa_raw as usize);
+
+        // Exactly the original, but for .rdata covering the hole
+        let mut expected = image.unpacked();
+        PeEditor::new(&mut expected)
+            .unwrap()
+            .set_section_virtual_size(1, 0x2000)
+            .unwrap();
+        assert_eq!(unpacked.data, expected);
+    }
+
+    #[test]
+    fn keeps_ooa_as_first_section() {
+        let image = fixture::Image {
+            text: 0x2000,
+            rdata: 0x3000,
+            reloc: 0x4000,
+            size_of_image: 0x5000,
+            ..fixture::Image::DEFAULT
+        };
+        let protected = image.protected_at(&LAYOUTS[1], 0, 0x1000);
+        let unpacked = unpack(&protected, &[DlfSource::CipherKey(fixture::KEY.to_vec())]).unwrap();
+        assert!(unpacked.notes.contains(
+            &"Keeping .ooa, it's the first section and removing it would leave a hole in the image"
+                .to_owned()
+        ));
+        assert_eq!(unpacked.data.len(), protected.len());
+        let file = PeFile::from_bytes(&unpacked.data).unwrap();
+        let sections = file.section_headers().as_slice();
+        assert_eq!(sections.len(), 4);
+        assert_eq!(sections[0].name(), Ok(".ooa"));
+        assert_eq!(
+            PeEditor::new(&mut unpacked.data.clone())
+                .unwrap()
+                .entry_point()
+                .unwrap(),
+            0x2000
+        );
+        assert_eq!(file.imports().unwrap().iter().count(), 1);
+    }
+
+    #[test]
+    fn explains_directories_not_kept() {
+        // Like BFV, .ooa has no import or reloc directory
+        let image = fixture::Image::DEFAULT;
+        let section = Section {
+            import_dir: Default::default(),
+            reloc_dir: Default::default(),
+            ..image.section()
+        };
+        let mut protected = image.protect(image.unpacked(), &section, &LAYOUTS[1]);
+        let ooa_sections = PeFile::from_bytes(&protected)
+            .unwrap()
+            .section_headers()
+            .iter()
+            .len();
+
+        // The import directory still points into .ooa, so it has to stay
+        let unpacked = unpack(&protected, &[DlfSource::CipherKey(fixture::KEY.to_vec())]).unwrap();
+        for note in [
+            "ImportDir: not kept in .ooa, header entry 00004242 points into .ooa",
+            "RelocDir: not kept in .ooa, header entry is empty too",
+            "IATDir: restored 00002060 (0x10 bytes)",
+            "Keeping .ooa and SizeOfImage, still referenced by: data directory 1 (00004242)",
+        ] {
+            assert!(unpacked.notes.contains(&note.to_owned()), "{}", note);
+        }
+        let file = PeFile::from_bytes(&unpacked.data).unwrap();
+        assert_eq!(file.section_headers().iter().len(), ooa_sections);
+        assert_eq!(
+            size_of_image(file),
+            size_of_image(PeFile::from_bytes(&protected).unwrap())
+        );
+
+        // Once it points at the original descriptors .ooa goes
+        PeEditor::new(&mut protected)
+            .unwrap()
+            .set_data_directory(IMAGE_DIRECTORY_ENTRY_IMPORT, fixture::RDATA_VA, 0x28)
+            .unwrap();
+        let unpacked = unpack(&protected, &[DlfSource::CipherKey(fixture::KEY.to_vec())]).unwrap();
+        assert!(unpacked.notes.contains(
+            &"ImportDir: not kept in .ooa, kept header entry 00002000 (0x28 bytes)".to_owned()
+        ));
+        assert!(!unpacked
+            .notes
+            .iter()
+            .any(|note| note.starts_with("Keeping")));
+        let file = PeFile::from_bytes(&unpacked.data).unwrap();
+        assert_eq!(file.section_headers().iter().len(), ooa_sections - 1);
+        assert_eq!(size_of_image(file), fixture::SIZE_OF_IMAGE);
+        assert_eq!(file.imports().unwrap().iter().count(), 1);
+    }
+
+    #[test]
+    fn notes_mismatched_image_base() {
+        let mut protected = fixture::protected(&LAYOUTS[1]);
+        // OptionalHeader.ImageBase of the fixture
+        protected[0x70..0x78].copy_from_slice(&0x1_8000_0000u64.to_le_bytes());
+        let unpacked = unpack(&protected, &[DlfSource::CipherKey(fixture::KEY.to_vec())]).unwrap();
+        assert!(unpacked
+            .notes
+            .contains(&"ImageBase 180000000 doesn't match the one in .ooa (140000000)".to_owned()));
+    }
+
+    #[test]
+    fn fixes_checksum_and_clears_security() {
+        let mut protected = fixture::protected(&LAYOUTS[1]);
+        let certificate = protected.len() as u32;
+        protected.extend_from_slice(&[0x5A; 0x20]);
+        PeEditor::new(&mut protected)
+            .unwrap()
+            .set_data_directory(IMAGE_DIRECTORY_ENTRY_SECURITY, certificate, 0x20)
+            .unwrap();
+
+        // The relocated certificate would keep its entry
+        let unpacked = Unpacker {
+            clear_security: true,
+            fix_checksum: true,
+            ..Default::default()
+        }
+        .unpack(&protected, &[DlfSource::CipherKey(fixture::KEY.to_vec())])
+        .unwrap();
+        let mut data = unpacked.data.clone();
+        let pe = PeEditor::new(&mut data).unwrap();
+        assert_eq!(
+            pe.data_directory(IMAGE_DIRECTORY_ENTRY_SECURITY).unwrap(),
+            (0, 0)
+        );
+        assert!(unpacked
+            .notes
+            .contains(&"Cleared SECURITY directory".to_owned()));
+
+        // The original image and the overlay, but for the CheckSum
+        let mut expected = fixture::unpacked();
+        expected.extend_from_slice(&[0x5A; 0x20]);
+        let checksum_off = pe.checksum_offset();
+        let checksum = pe_checksum(&expected, checksum_off);
+        assert_ne!(checksum, 0);
+        PeEditor::new(&mut expected)
+            .unwrap()
+            .set_checksum(checksum)
+            .unwrap();
+        assert_eq!(unpacked.data, expected);
+        assert!(unpacked
+            .notes
+            .contains(&format!("Recomputed CheckSum: {:08X}", checksum)));
+    }
+
+    #[test]
+    fn rejects_wrong_key() {
+        let err = unpack(
+            &fixture::protected(&LAYOUTS[1]),
+            &[DlfSource::CipherKey(b"not-the-real-key".to_vec())],
+        )
+        .unwrap_err();
+        assert!(matches!(err, OoaError::WrongKey(_)), "{}", err);
+    }
+
+    #[test]
+    fn rejects_short_key() {
+        let err = unpack(
+            &fixture::protected(&LAYOUTS[1]),
+            &[DlfSource::CipherKey(fixture::KEY[..8].to_vec())],
+        )
+        .unwrap_err();
+        assert!(matches!(err, OoaError::KeyLength(8)), "{}", err);
+    }
+
+    #[test]
+    fn falls_back_on_malformed_dlf() {
+        // Stray bytes and no <ContentId>, roxmltree won't have it
+        let dlf = format!(
+            "\0\x01<License><CipherKey>{}</CipherKey><GameToken>",
+            base64::encode(fixture::KEY)
+        );
+        let unpacked = unpack(
+            &fixture::protected(&LAYOUTS[0]),
+            &[DlfSource::Encrypted(encrypt_dlf(
+                dlf.as_bytes(),
+                DlfLayout::Header,
+            ))],
+        )
+        .unwrap();
+        assert_eq!(unpacked.key, fixture::KEY);
+        assert!(unpacked.license.is_none());
+        assert!(unpacked
+            .notes
+            .iter()
+            .any(|note| note.starts_with("Invalid DLF license")));
+
+        let err = unpack(
+            &fixture::protected(&LAYOUTS[0]),
+            &[DlfSource::Encrypted(encrypt_dlf(
+                b"<License>",
+                DlfLayout::Header,
+            ))],
+        )
+        .unwrap_err();
+        assert!(matches!(err, OoaError::BadLicense(_)), "{}", err);
+    }
+
+    #[test]
+    fn skips_undecryptable_dlf() {
+        let dir = std::env::temp_dir().join(format!("rse_ooa_decrypt-bad-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let bad = dir.join(format!("{}.dlf", fixture::CONTENT_ID));
+        std::fs::write(&bad, [0x42; 0x50]).unwrap();
+        let protected = fixture::protected(&LAYOUTS[0]);
+        let skipped = format!("Skipping {}: {}", bad.display(), OoaError::BadDlf);
+
+        // On to the next source
+        let unpacked = unpack(
+            &protected,
+            &[
+                DlfSource::Dir(dir.clone()),
+                DlfSource::Encrypted(fixture::dlf()),
+            ],
+        )
+        .unwrap();
+        assert_eq!(unpacked.key, fixture::KEY);
+        assert!(unpacked.notes.contains(&skipped));
+
+        // And on to the cached one in the same directory
+        let cached = dir.join(format!("{}_cached.dlf", fixture::CONTENT_ID));
+        std::fs::write(&cached, fixture::dlf()).unwrap();
+        let unpacked = unpack(&protected, &[DlfSource::Dir(dir.clone())]).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(unpacked.dlf_path, Some(cached));
+        assert!(unpacked.notes.contains(&skipped));
+    }
+
+    #[test]
+    fn stores_used_dlf() {
+        let store =
+            std::env::temp_dir().join(format!("rse_ooa_decrypt-store-{}", std::process::id()));
+        let protected = fixture::protected(&LAYOUTS[0]);
+        let unpacker = Unpacker {
+            dlf_store: Some(store.clone()),
+            ..Default::default()
+        };
+        let unpacked = unpacker
+            .unpack(&protected, &[DlfSource::Encrypted(fixture::dlf())])
+            .unwrap();
+        let stored = store.join(format!("{}.dlf", fixture::CONTENT_ID));
+        assert!(unpacked
+            .notes
+            .contains(&format!("Stored DLF as {}", stored.display())));
+
+        // Found there the next time, and not stored again
+        let unpacked = unpacker
+            .unpack(&protected, &[DlfSource::Dir(store.clone())])
+            .unwrap();
+        std::fs::remove_dir_all(&store).unwrap();
+        assert_eq!(unpacked.dlf_path, Some(stored));
+        assert_eq!(unpacked.dlf.as_deref(), Some(&fixture::dlf_plain()[..]));
+        assert!(!unpacked.notes.iter().any(|note| note.starts_with("Stored")));
+    }
+
+    #[test]
+    fn resolves_restored_imports() {
+        let unpacked = unpack(
+            &fixture::protected(&LAYOUTS[0]),
+            &[DlfSource::CipherKey(fixture::KEY.to_vec())],
+        )
+        .unwrap();
+        let imports = unpacked.imports().unwrap();
+        assert_eq!(imports.len(), 1);
+        assert_eq!(imports[0].dll, "KERNEL32.dll");
+        assert_eq!(
+            imports[0].functions,
+            [ImportedFunction::ByName {
+                hint: 0,
+                name: "ExitProcess".to_owned()
+            }]
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Decrypted DLF contents
+//!
+//! A DLF is an XML `<License>` document with one element per field, only
+//! `ContentId` and `CipherKey` are needed for unpacking.
+
+use std::fmt;
+
+use crate::error::OoaError;
+
+/// Elements that have their own `License` field
+const KNOWN_FIELDS: [&str; 5] = [
+    "ContentId",
+    "CipherKey",
+    "MachineHash",
+    "StartTime",
+    "LicenseType",
+];
+
+/// Names seen for the expiry element, the first one present is used
+const EXPIRY_FIELDS: [&str; 2] = ["EndTime", "ExpirationTime"];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct License {
+    pub content_id: String,
+    /// Decoded AES-128 key
+    pub cipher_key: Vec<u8>,
+    pub machine_hash: Option<String>,
+    pub start_time: Option<String>,
+    pub expiry_time: Option<String>,
+    pub license_type: Option<String>,
+    /// Every other element, in document order
+    pub other: Vec<(String, String)>,
+}
+
+impl License {
+    /// Parses a decrypted DLF
+    pub fn parse(dlf: &[u8]) -> Result<License, OoaError> {
+        let text = std::str::from_utf8(dlf)
+            .map_err(|_| OoaError::BadLicense("not UTF-8".to_owned()))?
+            .trim_start_matches('\u{feff}')
+            .trim_end_matches(['\0', '\r', '\n', ' ']);
+        let document = roxmltree::Document::parse(text)
+            .map_err(|err| OoaError::BadLicense(err.to_string()))?;
+        let root = document.root_element();
+        if root.tag_name().name() != "License" {
+            return Err(OoaError::BadLicense(format!(
+                "root element is <{}>",
+                root.tag_name().name()
+            )));
+        }
+
+        let field = |name: &str| {
+            root.children()
+                .find(|n| n.has_tag_name(name))
+                .map(|n| n.text().unwrap_or("").trim().to_owned())
+        };
+        let content_id =
+            field("ContentId").ok_or_else(|| OoaError::BadLicense("no <ContentId>".to_owned()))?;
+        let cipher_key = field("CipherKey").ok_or(OoaError::MissingCipherKey)?;
+        let cipher_key = base64::decode(&cipher_key)
+            .ok()
+            .filter(|key| key.len() == 16)
+            .ok_or_else(|| OoaError::BadLicense(format!("invalid <CipherKey> {}", cipher_key)))?;
+        // Should both be there, the other one ends up in `other`
+        let expiry = EXPIRY_FIELDS.iter().find(|name| field(name).is_some());
+        let other = root
+            .children()
+            .filter(|n| {
+                let name = n.tag_name().name();
+                n.is_element() && !KNOWN_FIELDS.contains(&name) && expiry != Some(&name)
+            })
+            .map(|n| {
+                (
+                    n.tag_name().name().to_owned(),
+                    n.text().unwrap_or("").trim().to_owned(),
+                )
+            })
+            .collect();
+        Ok(License {
+            content_id,
+            cipher_key,
+            machine_hash: field("MachineHash"),
+            start_time: field("StartTime"),
+            expiry_time: expiry.and_then(|name| field(name)),
+            license_type: field("LicenseType"),
+            other,
+        })
+    }
+}
+
+impl fmt::Display for License {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_owned());
+        writeln!(f, "Content ID:   {}", self.content_id)?;
+        write!(f, "CipherKey:    ")?;
+        for byte in &self.cipher_key {
+            write!(f, "{:02X}", byte)?;
+        }
+        writeln!(f)?;
+        writeln!(f, "Machine hash: {}", optional(&self.machine_hash))?;
+        writeln!(f, "Start time:   {}", optional(&self.start_time))?;
+        writeln!(f, "Expiry time:  {}", optional(&self.expiry_time))?;
+        write!(f, "License type: {}", optional(&self.license_type))?;
+        for (name, value) in &self.other {
+            write!(f, "\n{}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+
+    #[test]
+    fn parses_fixture() {
+        let license = License::parse(&fixture::dlf_plain()).unwrap();
+        assert_eq!(license.content_id, fixture::CONTENT_ID);
+        assert_eq!(license.cipher_key, fixture::KEY);
+        assert_eq!(license.machine_hash.as_deref(), Some("1"));
+        assert_eq!(license.expiry_time.as_deref(), Some("2099-12-31T23:59:59Z"));
+        assert_eq!(
+            license.other,
+            [("GameToken".to_owned(), "token".to_owned())]
+        );
+    }
+
+    #[test]
+    fn keeps_second_expiry_time() {
+        let license = License::parse(
+            b"<License><ContentId>1</ContentId>\
+              <CipherKey>AAAAAAAAAAAAAAAAAAAAAA==</CipherKey>\
+              <ExpirationTime>2030-01-01T00:00:00Z</ExpirationTime>\
+              <EndTime>2099-12-31T23:59:59Z</EndTime></License>",
+        )
+        .unwrap();
+        assert_eq!(license.expiry_time.as_deref(), Some("2099-12-31T23:59:59Z"));
+        assert_eq!(
+            license.other,
+            [(
+                "ExpirationTime".to_owned(),
+                "2030-01-01T00:00:00Z".to_owned()
+            )]
+        );
+
+        let license = License::parse(
+            b"<License><ContentId>1</ContentId>\
+              <CipherKey>AAAAAAAAAAAAAAAAAAAAAA==</CipherKey>\
+              <ExpirationTime>2030-01-01T00:00:00Z</ExpirationTime></License>",
+        )
+        .unwrap();
+        assert_eq!(license.expiry_time.as_deref(), Some("2030-01-01T00:00:00Z"));
+        assert!(license.other.is_empty());
+    }
+
+    #[test]
+    fn missing_fields() {
+        assert!(matches!(
+            License::parse(b"<License><ContentId>1</ContentId></License>"),
+            Err(OoaError::MissingCipherKey)
+        ));
+        assert!(matches!(
+            License::parse(b"<License><CipherKey>AAAAAAAAAAAAAAAAAAAAAA==</CipherKey></License>"),
+            Err(OoaError::BadLicense(_))
+        ));
+        assert!(matches!(
+            License::parse(b"<License><ContentId>1</ContentId"),
+            Err(OoaError::BadLicense(_))
+        ));
+    }
+}
@@ -1,227 +1,315 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use pelite::pe64::{Pe, PeFile};
 use pelite::FileMap;
 
-use crate::util::{aes_decrypt_inplace, decrypt_dlf, dlf_get_cipher, get_dlf_auto};
+use rse_ooa_decrypt::config::Config;
+use rse_ooa_decrypt::crack::crack;
+use rse_ooa_decrypt::imports::ImportedFunction;
+use rse_ooa_decrypt::license::License;
+use rse_ooa_decrypt::overlay::OverlayMode;
+use rse_ooa_decrypt::util::decrypt_dlf;
+use rse_ooa_decrypt::{read_ooa_hash, DlfSource, OoaError, Registry, Unpacker};
 
-mod apex;
-mod bf2042;
-mod ooa;
-mod skate_cpt;
-mod titanfall2;
-mod util;
+/// Flags without a value
+const FLAGS: [&str; 7] = [
+    "--versions",
+    "--verify-crc",
+    "--strict-crc",
+    "--force",
+    "--clear-security",
+    "--fix-checksum",
+    "--imports",
+];
 
-fn get_ooa_hash(data: &[u8]) -> Option<[u8; 20]> {
-    if data.len() < 0x3E {
-        None
-    } else {
-        let mut hash = [0u8; 20];
-        hash.copy_from_slice(&data[0x2A..0x3E]);
-        Some(hash)
-    }
-}
+/// Flags taking a value, as `--flag=value` or `--flag value`
+const VALUE_FLAGS: [&str; 3] = ["--overlay", "--dlf-dir", "--dlf-store"];
 
-fn main() {
-    // Nicer Release error messages, I guess?
-    #[cfg(not(debug_assertions))]
-    std::panic::set_hook(Box::new(|panic_info: &std::panic::PanicInfo| {
-        if let Some(s) = panic_info.payload().downcast_ref::<&str>() {
-            println!("{}", s);
-        } else {
-            let string = panic_info.to_string();
-            println!(
-                "{}",
-                &string[13..string.rfind('\'').unwrap_or(string.len())]
-            );
-        }
-    }));
-
-    let file = std::env::args().nth(1);
-    // let key = std::env::args().nth(2);
-    if let Some(path) = file {
-        let file_map = FileMap::open(&path).expect("Error mapping PE file!");
-        let file = PeFile::from_bytes(file_map.as_ref()).expect("Error parsing PE file!");
-        // let optional_header = file.optional_header();
-        let (sections_num, section_header) = {
-            let section_header = file.section_headers().iter();
-            (
-                section_header.len(),
-                section_header
-                    .last()
-                    .expect("Invalid PE File! Zero Sections"),
-            )
-        };
-        debug_assert_eq!(section_header.name().unwrap(), ".ooa");
-        let section = &(file_map.as_ref())[section_header.PointerToRawData as usize
-            ..section_header.PointerToRawData as usize + section_header.SizeOfRawData as usize];
-        let hash = get_ooa_hash(section).expect("Invalid .ooa section!");
-        let section = match hash {
-            titanfall2::HASH => {
-                eprintln!("Parsing 5.00.01.35");
-                titanfall2::parse(section)
-            }
-            apex::HASH_S11_1 => {
-                eprintln!("Parsing 5.02.04.66");
-                apex::parse_s11_1(section)
-            }
-            skate_cpt::HASH => {
-                eprintln!("Parsing Skate CPT.");
-                skate_cpt::parse(section)
-            }
-            bf2042::HASH => {
-                eprintln!("Parsing BF2042");
-                bf2042::parse(section)
-            }
-            _ => {
-                unreachable!("Unknown .ooa version hash! {:X?}", hash);
-            }
-        };
-        // This shit will fail on BFV...
-        if (section.image_base != 0) {
-            debug_assert_eq!(file.optional_header().ImageBase, section.image_base);
-        }
-        debug_assert_eq!(
-            file.optional_header().SizeOfImage - 0x1000,
-            section.size_of_image
-        );
-        println!("{:#X?}", section);
-
-        let dlf = if let Some(dlf) = get_dlf_auto(&section.content_id) {
-            Some(dlf)
-        } else if let Some(path) = std::env::args().nth(2) {
-            if let Ok(data) = std::fs::read(path) {
-                Some(decrypt_dlf(&data))
-            } else {
-                None
+/// Splits the command line into flags (value ones as `--flag=value`) and the rest
+///
+/// Unknown options, missing values and overlay modes are usage errors.
+fn split_args(args: impl Iterator<Item = String>) -> Result<(Vec<String>, Vec<String>), String> {
+    let (mut flags, mut rest) = (Vec::new(), Vec::new());
+    let mut args = args.peekable();
+    while let Some(arg) = args.next() {
+        let flag = if !arg.starts_with("--") {
+            rest.push(arg);
+            continue;
+        } else if FLAGS.contains(&arg.as_str())
+            || VALUE_FLAGS
+                .iter()
+                .any(|flag| arg.strip_prefix(flag).is_some_and(|v| v.starts_with('=')))
+        {
+            arg
+        } else if VALUE_FLAGS.contains(&arg.as_str()) {
+            match args.next_if(|value| !value.starts_with("--")) {
+                Some(value) => format!("{}={}", arg, value),
+                None => return Err(format!("{} needs a value", arg)),
             }
         } else {
-            None
-        }
-        .expect("Can't find correct DLF file!");
-        println!("DLF: {}", String::from_utf8_lossy(&dlf));
-        let dlf_key = dlf_get_cipher(&dlf).expect("Failed to get CipherKey from DLF!");
-        println!("Key: {:?}", &dlf_key);
-
-        let weird_binary = !((section.import_dir.va != 0)
-            && (section.import_dir.size != 0)
-            && (section.reloc_dir.va != 0)
-            && (section.reloc_dir.size != 0)
-            && (section.iat_dir.va != 0)
-            && (section.iat_dir.size != 0));
-
-        let mut new = if weird_binary {
-            file_map.as_ref()[..].to_vec()
-        } else {
-            file_map.as_ref()[0..section_header.PointerToRawData as usize].to_vec()
+            return Err(format!("Unknown option: {}", arg));
         };
-        let e_lfanew = file.dos_header().e_lfanew as usize;
-        let file_header_size = 24usize;
-        let optional_header_size = file.file_header().SizeOfOptionalHeader as usize;
-
-        // Decrypt every section...
-        for block in section.enc_blocks {
-            let section = file
-                .section_headers()
-                .as_slice()
+        if let Some(mode) = flag.strip_prefix("--overlay=") {
+            mode.parse::<OverlayMode>()?;
+        }
+        flags.push(flag);
+    }
+    Ok((flags, rest))
+}
+
+/// `<name>-unpacked.exe`, or `.dll`, in the working directory
+fn output_name(path: &str, is_dll: bool) -> String {
+    let extension = if is_dll { "dll" } else { "exe" };
+    if let Some(stem) = Path::new(path).file_stem() {
+        format!("{}-unpacked.{}", stem.to_str().unwrap_or(""), extension)
+    } else {
+        format!("unpacked.{}", extension)
+    }
+}
+
+/// The DLF we were given first, then `--dlf-dir`, the environment and config file,
+/// the store and the usual places
+fn dlf_sources(
+    dlf_path: Option<&String>,
+    flags: &[&str],
+    config_dirs: Vec<PathBuf>,
+    store: Option<PathBuf>,
+) -> Vec<DlfSource> {
+    dlf_path
+        .map(|path| DlfSource::File(path.into()))
+        .into_iter()
+        .chain(
+            flags
                 .iter()
-                .find(|s| s.VirtualAddress == block.va)
-                .expect("Failed to find section for decryption!");
-            let mut iv = [0u8; 16];
-            iv[..].copy_from_slice(
-                &new[section.PointerToRawData as usize - 0x10..section.PointerToRawData as usize],
-            );
-            aes_decrypt_inplace(
-                &dlf_key,
-                &iv,
-                &mut new[section.PointerToRawData as usize
-                    ..section.PointerToRawData as usize + section.SizeOfRawData as usize],
-            );
-            // fix padding of one block
-            if new[section.PointerToRawData as usize + section.SizeOfRawData as usize - 0x10
-                ..section.PointerToRawData as usize + section.SizeOfRawData as usize]
-                == [0x10u8; 16]
-            {
-                new[section.PointerToRawData as usize + section.SizeOfRawData as usize - 0x10
-                    ..section.PointerToRawData as usize + section.SizeOfRawData as usize]
-                    .copy_from_slice(&[0u8; 16]);
-            }
+                .filter_map(|f| f.strip_prefix("--dlf-dir="))
+                .map(|dir| DlfSource::Dir(dir.into())),
+        )
+        .chain(config_dirs.into_iter().map(DlfSource::Dir))
+        .chain(store.map(DlfSource::Dir))
+        .chain(std::iter::once(DlfSource::Auto))
+        .collect()
+}
+
+fn run(path: &str, dlf_path: Option<&String>, flags: &[&str]) -> Result<(), OoaError> {
+    let file_map = FileMap::open(path)?;
+    // A broken config file shouldn't get in the way when we're told where the DLF is
+    let explicit_dlf = dlf_path.is_some() || flags.iter().any(|f| f.starts_with("--dlf-dir="));
+    let config = match Config::load() {
+        Ok(config) => config,
+        Err(err) if explicit_dlf => {
+            eprintln!("{}, ignoring it", err);
+            Config::default()
         }
+        Err(err) => return Err(err),
+    };
+    let mut unpacker = Unpacker {
+        verify_crc: flags.contains(&"--verify-crc"),
+        strict_crc: flags.contains(&"--strict-crc"),
+        force: flags.contains(&"--force"),
+        clear_security: flags.contains(&"--clear-security"),
+        fix_checksum: flags.contains(&"--fix-checksum"),
+        dlf_store: flags
+            .iter()
+            .find_map(|f| f.strip_prefix("--dlf-store="))
+            .map(Into::into)
+            .or(config.dlf_store),
+        ..Default::default()
+    };
+    // Already checked by split_args
+    if let Some(Ok(mode)) = flags
+        .iter()
+        .find_map(|f| f.strip_prefix("--overlay="))
+        .map(str::parse)
+    {
+        unpacker.overlay = mode;
+    }
 
-        if !weird_binary {
-            // decrement sections count
-            let sections_num_off = e_lfanew + 6;
-            new[sections_num_off..sections_num_off + 2]
-                .copy_from_slice(&(sections_num as u16 - 1).to_le_bytes());
-
-            // Zero section out
-            let section_data_off = e_lfanew
-                + file_header_size
-                + optional_header_size
-                + (sections_num as usize - 1) * 0x28;
-            new[section_data_off..section_data_off + 0x28].fill(0);
-
-            // fix size of image
-            let size_of_image_off = e_lfanew + file_header_size + 56;
-            if section.size_of_image != 0 {
-                new[size_of_image_off..size_of_image_off + 4]
-                    .copy_from_slice(&section.size_of_image.to_le_bytes());
-            } else {
-                // manual?
-                let new_val = file.optional_header().SizeOfImage - 0x1000;
-                eprintln!("Manual fixing of SizeOfImage: {:08X}", new_val);
-                new[size_of_image_off..size_of_image_off + 4]
-                    .copy_from_slice(&new_val.to_le_bytes());
+    let dlf_sources = dlf_sources(dlf_path, flags, config.dlf_dirs, unpacker.dlf_store.clone());
+    let unpacked = unpacker.unpack(file_map.as_ref(), &dlf_sources)?;
+    eprintln!("Parsing {}", unpacked.version);
+    println!("{:#X?}", unpacked.section);
+    if let Some(dlf) = &unpacked.dlf {
+        println!("DLF: {}", String::from_utf8_lossy(dlf));
+    }
+    println!("Key: {:?}", &unpacked.key);
+    for note in &unpacked.notes {
+        eprintln!("{}", note);
+    }
+    std::fs::write(output_name(path, unpacked.is_dll), &unpacked.data)?;
+    if flags.contains(&"--imports") {
+        for import in unpacked.imports()? {
+            println!("{}", import.dll);
+            for function in import.functions {
+                match function {
+                    ImportedFunction::ByName { hint, name } => println!("    {} ({})", name, hint),
+                    ImportedFunction::ByOrdinal(ordinal) => println!("    #{}", ordinal),
+                }
             }
-        } else {
-            eprintln!("Weird Binary detected, not fixing sections and SizeOfImage!");
         }
+    }
+    Ok(())
+}
 
-        // fix OEP
-        let oep_off = e_lfanew + file_header_size + 16;
-        new[oep_off..oep_off + 4].copy_from_slice(&(section.oep as u32).to_le_bytes());
-
-        if (section.import_dir.va != 0) && (section.import_dir.size != 0) {
-            // fix import directory
-            let import_dir_off = e_lfanew + file_header_size + 120;
-            new[import_dir_off..import_dir_off + 4]
-                .copy_from_slice(&section.import_dir.va.to_le_bytes());
-            new[import_dir_off + 4..import_dir_off + 8]
-                .copy_from_slice(&section.import_dir.size.to_le_bytes());
-        } else {
-            eprintln!("Weird ImportDir: {:?}", section.import_dir)
-        }
+fn parse_hex_hash(hex: &str) -> Option<[u8; 20]> {
+    if hex.len() != 40 || !hex.is_ascii() {
+        return None;
+    }
+    let mut hash = [0u8; 20];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(hash)
+}
 
-        if (section.reloc_dir.va != 0) && (section.reloc_dir.size != 0) {
-            // fix reloc directory
-            let reloc_dir_off = e_lfanew + file_header_size + 152;
-            new[reloc_dir_off..reloc_dir_off + 4]
-                .copy_from_slice(&section.reloc_dir.va.to_le_bytes());
-            new[reloc_dir_off + 4..reloc_dir_off + 8]
-                .copy_from_slice(&section.reloc_dir.size.to_le_bytes());
-        } else {
-            eprintln!("Weird RelocDir: {:?}", section.reloc_dir)
+/// `identify <packed.exe | sha1 hex>`
+fn identify(target: &str) -> Result<(), OoaError> {
+    let hash = if let Some(hash) = parse_hex_hash(target) {
+        hash
+    } else {
+        read_ooa_hash(FileMap::open(target)?.as_ref())?
+    };
+    if let Some(parser) = Registry::default().by_hash(&hash) {
+        println!("{} ({}), supported", parser.version(), parser.name());
+        return Ok(());
+    }
+    eprintln!("Unknown hash {:X?}, brute-forcing...", hash);
+    match crack(&hash) {
+        Some(version) => {
+            println!("{}, not supported yet", version);
+            Ok(())
         }
+        None => Err(OoaError::UnknownVersion(hash)),
+    }
+}
 
-        if (section.iat_dir.va != 0) && (section.iat_dir.size != 0) {
-            // fix iat directory
-            let iat_off = e_lfanew + file_header_size + 208;
-            new[iat_off..iat_off + 4].copy_from_slice(&section.iat_dir.va.to_le_bytes());
-            new[iat_off + 4..iat_off + 8].copy_from_slice(&section.iat_dir.size.to_le_bytes());
-        } else {
-            eprintln!("Weird IATDir: {:?}", section.iat_dir)
+/// `dlf show <file.dlf>`
+fn show_dlf(path: &str) -> Result<(), OoaError> {
+    let license = License::parse(&decrypt_dlf(&std::fs::read(path)?)?)?;
+    println!("{}", license);
+    Ok(())
+}
+
+fn main() {
+    let (flags, args) = match split_args(std::env::args().skip(1)) {
+        Ok(split) => split,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let flags = flags.iter().map(String::as_str).collect::<Vec<_>>();
+    let result = match args.first().map(String::as_str) {
+        None if flags == ["--versions"] => {
+            for parser in Registry::default().iter() {
+                println!("{} ({})", parser.version(), parser.name());
+            }
+            Ok(())
+        }
+        Some("identify") if args.len() == 2 => identify(&args[1]),
+        Some("dlf") if args.len() == 3 && args[1] == "show" => show_dlf(&args[2]),
+        Some(path) if path != "identify" && path != "dlf" => run(path, args.get(1), &flags),
+        _ => {
+            eprint!("Invalid usage!");
+            std::process::exit(1);
         }
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(err.exit_code());
+    }
+}
 
-        std::fs::write(
-            if let Some(stem) = Path::new(&path).file_stem() {
-                stem.to_str().unwrap_or("").to_owned() + "-unpacked.exe"
-            } else {
-                "unpacked.exe".to_owned()
-            },
-            &new,
-        )
-        .expect("Error writing file!");
-    } else {
-        eprint!("Invalid usage!");
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(args: &[&str]) -> Result<(Vec<String>, Vec<String>), String> {
+        split_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn splits_flags_and_values() {
+        let (flags, rest) = split(&[
+            "r5apex.exe",
+            "--force",
+            "--dlf-dir",
+            "/mnt/licenses",
+            "--dlf-dir=/etc/licenses",
+            "--overlay",
+            "strip",
+            "license.dlf",
+        ])
+        .unwrap();
+        assert_eq!(
+            flags,
+            [
+                "--force",
+                "--dlf-dir=/mnt/licenses",
+                "--dlf-dir=/etc/licenses",
+                "--overlay=strip"
+            ]
+        );
+        assert_eq!(rest, ["r5apex.exe", "license.dlf"]);
+    }
+
+    #[test]
+    fn rejects_bad_options() {
+        assert_eq!(
+            split(&["r5apex.exe", "--dlf-dirs=/x"]),
+            Err("Unknown option: --dlf-dirs=/x".to_owned())
+        );
+        assert_eq!(
+            split(&["r5apex.exe", "--fix-checksum=yes"]),
+            Err("Unknown option: --fix-checksum=yes".to_owned())
+        );
+        assert_eq!(
+            split(&["r5apex.exe", "--dlf-dir"]),
+            Err("--dlf-dir needs a value".to_owned())
+        );
+        assert_eq!(
+            split(&["r5apex.exe", "--dlf-store", "--force"]),
+            Err("--dlf-store needs a value".to_owned())
+        );
+        assert_eq!(
+            split(&["r5apex.exe", "--overlay=move"]),
+            Err("Unknown overlay mode: move".to_owned())
+        );
+        assert_eq!(
+            split(&["r5apex.exe", "--overlay", "move"]),
+            Err("Unknown overlay mode: move".to_owned())
+        );
+    }
+
+    #[test]
+    fn names_output_by_image_kind() {
+        assert_eq!(output_name("game/r5apex.exe", false), "r5apex-unpacked.exe");
+        assert_eq!(output_name("bin/engine.dll", true), "engine-unpacked.dll");
+        // Whatever the protected file was called
+        assert_eq!(output_name("engine.bin", true), "engine-unpacked.dll");
+        assert_eq!(output_name("..", false), "unpacked.exe");
+    }
+
+    #[test]
+    fn tries_given_dlf_first() {
+        let dlf = "given.dlf".to_owned();
+        let sources = dlf_sources(
+            Some(&dlf),
+            &["--force", "--dlf-dir=/mnt/licenses"],
+            vec!["/etc/licenses".into()],
+            Some("/var/cache/dlf".into()),
+        );
+        let expected = [
+            "File(\"given.dlf\")",
+            "Dir(\"/mnt/licenses\")",
+            "Dir(\"/etc/licenses\")",
+            "Dir(\"/var/cache/dlf\")",
+            "Auto",
+        ];
+        assert_eq!(
+            sources
+                .iter()
+                .map(|source| format!("{:?}", source))
+                .collect::<Vec<_>>(),
+            expected
+        );
     }
 }
@@ -1,9 +1,12 @@
-use std::io::{Read, Seek};
+use std::io::{Read, Seek, Write};
 
-use byteorder::{ReadBytesExt, LE};
+use crate::error::OoaError;
+use crate::reloc::RelocBlock;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
 
 /// Public structure containing all we need to restore 99% of the binary
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct Section {
     pub content_id: String,
     pub oep: usize,
@@ -13,9 +16,30 @@ pub struct Section {
     pub import_dir: DataDir,
     pub iat_dir: DataDir,
     pub reloc_dir: DataDir,
+    /// Original `IMAGE_IMPORT_DESCRIPTOR`s, without the terminator
+    pub imports: Vec<Import>,
+    /// Original IAT thunks, without the terminator
+    pub iat_thunks: Vec<Thunk>,
+    /// Original `OriginalFirstThunk` thunks, without the terminator
+    pub original_thunks: Vec<Thunk>,
+    /// RVA of the original `IMAGE_TLS_DIRECTORY64`
+    pub tls: u32,
+    /// RVA of the TLS callback array
+    pub tls_callback: u32,
+    /// VA of the first TLS callback
+    pub tls_first_callback: u64,
+    /// VAs of the callbacks after the first one, only some versions keep those
+    pub tls_callbacks: Vec<u64>,
+    /// Whether `tls_callbacks` is the whole list, see [`Layout`](crate::layout::Layout)
+    pub tls_callback_list: bool,
+    /// Original base relocations
+    pub relocs: Vec<RelocBlock>,
+    /// Anything odd the parser noticed, passed on to [`UnpackedImage::notes`](crate::UnpackedImage::notes)
+    pub notes: Vec<String>,
 }
 
-#[derive(Debug)]
+/// `IMAGE_IMPORT_DESCRIPTOR`
+#[derive(Debug, Clone)]
 pub struct Import {
     pub characteristics: u32,
     pub timedatestamp: u32,
@@ -24,20 +48,48 @@ pub struct Import {
     pub fthunk: u32,
 }
 
-#[derive(Debug)]
+impl Import {
+    pub fn to_bytes(&self) -> [u8; 20] {
+        let mut bytes = [0u8; 20];
+        for (i, value) in [
+            self.characteristics,
+            self.timedatestamp,
+            self.forwarder_chain,
+            self.name,
+            self.fthunk,
+        ]
+        .iter()
+        .enumerate()
+        {
+            bytes[i * 4..i * 4 + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+/// 64 bit thunk, split in halves
+///
+/// PE32 images only use `function`.
+#[derive(Debug, Clone)]
 pub struct Thunk {
     pub function: u32,
     pub data_addr: u32,
 }
 
-#[derive(Debug)]
+impl Thunk {
+    pub fn value(&self) -> u64 {
+        self.function as u64 | (self.data_addr as u64) << 32
+    }
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct DataDir {
     pub va: u32,
     pub size: u32,
 }
 
 /// Encrypted Block structure from `.ooa` section
-#[derive(Debug)]
+#[derive(Debug, Clone, Default)]
 pub struct EncBlock {
     pub va: u32,
     pub raw_size: u32,
@@ -52,42 +104,101 @@ pub struct EncBlock {
     pub pad3: u32,        // 0
 }
 
-pub fn read_import<T: Read + Seek + ReadBytesExt>(cursor: &mut T) -> Import {
-    Import {
-        characteristics: cursor.read_u32::<LE>().unwrap(),
-        timedatestamp: cursor.read_u32::<LE>().unwrap(),
-        forwarder_chain: cursor.read_u32::<LE>().unwrap(),
-        name: cursor.read_u32::<LE>().unwrap(),
-        fthunk: cursor.read_u32::<LE>().unwrap(),
-    }
+pub fn read_import<T: Read + Seek + ReadBytesExt>(cursor: &mut T) -> std::io::Result<Import> {
+    Ok(Import {
+        characteristics: cursor.read_u32::<LE>()?,
+        timedatestamp: cursor.read_u32::<LE>()?,
+        forwarder_chain: cursor.read_u32::<LE>()?,
+        name: cursor.read_u32::<LE>()?,
+        fthunk: cursor.read_u32::<LE>()?,
+    })
 }
 
-pub fn read_thunk<T: Read + Seek + ReadBytesExt>(cursor: &mut T) -> Thunk {
-    Thunk {
-        function: cursor.read_u32::<LE>().unwrap(),
-        data_addr: cursor.read_u32::<LE>().unwrap(),
-    }
+pub fn read_thunk<T: Read + Seek + ReadBytesExt>(cursor: &mut T) -> std::io::Result<Thunk> {
+    Ok(Thunk {
+        function: cursor.read_u32::<LE>()?,
+        data_addr: cursor.read_u32::<LE>()?,
+    })
 }
 
-pub fn read_data_dir<T: Read + Seek + ReadBytesExt>(cursor: &mut T) -> DataDir {
-    DataDir {
-        va: cursor.read_u32::<LE>().unwrap(),
-        size: cursor.read_u32::<LE>().unwrap(),
-    }
+pub fn read_data_dir<T: Read + Seek + ReadBytesExt>(cursor: &mut T) -> std::io::Result<DataDir> {
+    Ok(DataDir {
+        va: cursor.read_u32::<LE>()?,
+        size: cursor.read_u32::<LE>()?,
+    })
 }
 
-pub fn read_enc_block<T: Read + Seek + ReadBytesExt>(cursor: &mut T) -> EncBlock {
-    EncBlock {
-        va: cursor.read_u32::<LE>().unwrap(),
-        raw_size: cursor.read_u32::<LE>().unwrap(),
-        virtual_size: cursor.read_u32::<LE>().unwrap(),
-        unk: cursor.read_u32::<LE>().unwrap(),
-        crc: cursor.read_u32::<LE>().unwrap(),
-        unk2: cursor.read_u32::<LE>().unwrap(), // 0
-        crc2: cursor.read_u32::<LE>().unwrap(),
-        pad: cursor.read_u32::<LE>().unwrap(),         // 0
-        file_offset: cursor.read_u32::<LE>().unwrap(), // 0
-        pad2: cursor.read_u64::<LE>().unwrap(),        // 0
-        pad3: cursor.read_u32::<LE>().unwrap(),        // 0
+pub fn read_enc_block<T: Read + Seek + ReadBytesExt>(cursor: &mut T) -> std::io::Result<EncBlock> {
+    Ok(EncBlock {
+        va: cursor.read_u32::<LE>()?,
+        raw_size: cursor.read_u32::<LE>()?,
+        virtual_size: cursor.read_u32::<LE>()?,
+        unk: cursor.read_u32::<LE>()?,
+        crc: cursor.read_u32::<LE>()?,
+        unk2: cursor.read_u32::<LE>()?, // 0
+        crc2: cursor.read_u32::<LE>()?,
+        pad: cursor.read_u32::<LE>()?,         // 0
+        file_offset: cursor.read_u32::<LE>()?, // 0
+        pad2: cursor.read_u64::<LE>()?,        // 0
+        pad3: cursor.read_u32::<LE>()?,        // 0
+    })
+}
+
+/// Reads the zero terminated UTF-16 content ID at `0x42`
+pub fn read_content_id(data: &[u8]) -> Result<String, OoaError> {
+    let raw = data.get(0x42..0x241).ok_or(OoaError::Truncated)?;
+    let chars = raw
+        .chunks_exact(2)
+        .map(|c| u16::from_le_bytes([c[0], c[1]]))
+        .take_while(|&c| c != 0)
+        .collect::<Vec<_>>();
+    Ok(String::from_utf16_lossy(&chars))
+}
+
+pub fn write_thunk<T: Write + WriteBytesExt>(cursor: &mut T, thunk: &Thunk) -> std::io::Result<()> {
+    cursor.write_u32::<LE>(thunk.function)?;
+    cursor.write_u32::<LE>(thunk.data_addr)
+}
+
+pub fn write_data_dir<T: Write + WriteBytesExt>(
+    cursor: &mut T,
+    dir: &DataDir,
+) -> std::io::Result<()> {
+    cursor.write_u32::<LE>(dir.va)?;
+    cursor.write_u32::<LE>(dir.size)
+}
+
+pub fn write_enc_block<T: Write + WriteBytesExt>(
+    cursor: &mut T,
+    block: &EncBlock,
+) -> std::io::Result<()> {
+    cursor.write_u32::<LE>(block.va)?;
+    cursor.write_u32::<LE>(block.raw_size)?;
+    cursor.write_u32::<LE>(block.virtual_size)?;
+    cursor.write_u32::<LE>(block.unk)?;
+    cursor.write_u32::<LE>(block.crc)?;
+    cursor.write_u32::<LE>(block.unk2)?;
+    cursor.write_u32::<LE>(block.crc2)?;
+    cursor.write_u32::<LE>(block.pad)?;
+    cursor.write_u32::<LE>(block.file_offset)?;
+    cursor.write_u64::<LE>(block.pad2)?;
+    cursor.write_u32::<LE>(block.pad3)
+}
+
+/// Writes `content_id` as zero terminated UTF-16 at `0x42`, `data` has to be at least `0x242` long
+pub fn write_content_id(data: &mut [u8], content_id: &str) -> Result<(), OoaError> {
+    let chars = content_id.encode_utf16().collect::<Vec<_>>();
+    // Room for the terminator
+    if chars.len() >= (0x241 - 0x42) / 2 {
+        return Err(OoaError::Repack(format!(
+            "content ID is {} UTF-16 units long",
+            chars.len()
+        )));
+    }
+    let raw = data.get_mut(0x42..0x241).ok_or(OoaError::Truncated)?;
+    raw.fill(0);
+    for (dst, c) in raw.chunks_exact_mut(2).zip(chars) {
+        dst.copy_from_slice(&c.to_le_bytes());
     }
+    Ok(())
 }
@@ -0,0 +1,210 @@
+//! Data past the last section (signatures, installers appending stuff, ...)
+//!
+//! Removing `.ooa` drops its raw data, which would take the overlay with it or
+//! move it. The Authenticode certificate table usually lives there too and is the
+//! one data directory that uses a file offset instead of an RVA.
+
+use pelite::image::IMAGE_DIRECTORY_ENTRY_SECURITY;
+use pelite::PeFile;
+
+use crate::error::OoaError;
+use crate::pe::PeEditor;
+
+/// What to do with the overlay
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverlayMode {
+    /// Leave it at its original file offset, zero padding what `.ooa` took up
+    Keep,
+    /// Drop it
+    Strip,
+    /// Move it right after the last remaining section
+    Relocate,
+}
+
+impl std::str::FromStr for OverlayMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(OverlayMode::Keep),
+            "strip" => Ok(OverlayMode::Strip),
+            "relocate" => Ok(OverlayMode::Relocate),
+            _ => Err(format!("Unknown overlay mode: {}", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Overlay {
+    /// File offset
+    pub offset: usize,
+    pub size: usize,
+    /// `(file offset, size)` of the certificate table, if it's inside the overlay
+    pub certificate: Option<(u32, u32)>,
+}
+
+/// Finds data past the end of the last section's raw data
+pub fn find(file: PeFile, file_len: usize) -> Option<Overlay> {
+    let offset = file
+        .section_headers()
+        .iter()
+        .map(|s| s.PointerToRawData.saturating_add(s.SizeOfRawData) as usize)
+        .max()?;
+    if offset >= file_len {
+        return None;
+    }
+    let certificate = file
+        .data_directory()
+        .get(IMAGE_DIRECTORY_ENTRY_SECURITY)
+        .map(|dir| (dir.VirtualAddress, dir.Size))
+        .filter(|&(off, size)| {
+            size != 0
+                && off as usize >= offset
+                && (off as usize).saturating_add(size as usize) <= file_len
+        });
+    Some(Overlay {
+        offset,
+        size: file_len - offset,
+        certificate,
+    })
+}
+
+/// Applies `mode` to the unpacked file in `new`, returning what was done
+///
+/// `new` is either a full copy of `pe_bytes` or has `.ooa` removed and no overlay.
+pub fn apply(
+    mode: OverlayMode,
+    overlay: &Overlay,
+    pe_bytes: &[u8],
+    new: &mut Vec<u8>,
+) -> Result<String, OoaError> {
+    let in_place = new.len() >= overlay.offset + overlay.size;
+    let report = format!(
+        "Overlay of {:#X} bytes at {:#X}{}",
+        overlay.size,
+        overlay.offset,
+        if overlay.certificate.is_some() {
+            " (with certificate table)"
+        } else {
+            ""
+        }
+    );
+    match mode {
+        OverlayMode::Keep | OverlayMode::Relocate if in_place => {
+            Ok(format!("{}: kept in place", report))
+        }
+        OverlayMode::Keep => {
+            new.resize(overlay.offset, 0);
+            new.extend_from_slice(&pe_bytes[overlay.offset..]);
+            Ok(format!("{}: kept in place, padded where .ooa was", report))
+        }
+        OverlayMode::Relocate => {
+            // Keep the certificate table 8 byte aligned
+            while new.len() % 8 != overlay.offset % 8 {
+                new.push(0);
+            }
+            let new_offset = new.len();
+            new.extend_from_slice(&pe_bytes[overlay.offset..]);
+            if let Some((cert_off, cert_size)) = overlay.certificate {
+                let moved = (cert_off as usize - overlay.offset + new_offset) as u32;
+                PeEditor::new(new)?.set_data_directory(
+                    IMAGE_DIRECTORY_ENTRY_SECURITY,
+                    moved,
+                    cert_size,
+                )?;
+            }
+            Ok(format!("{}: relocated to {:#X}", report, new_offset))
+        }
+        OverlayMode::Strip => {
+            new.truncate(overlay.offset);
+            if overlay.certificate.is_some() {
+                PeEditor::new(new)?.set_data_directory(IMAGE_DIRECTORY_ENTRY_SECURITY, 0, 0)?;
+            }
+            Ok(format!("{}: stripped", report))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::LAYOUTS;
+    use crate::{fixture, DlfSource, Unpacker};
+
+    const JUNK: usize = 8;
+    const CERTIFICATE: u32 = 0x20;
+
+    /// `fixture::protected` with some junk and a certificate table appended
+    fn signed() -> Vec<u8> {
+        let mut data = fixture::protected(&LAYOUTS[1]);
+        let certificate = (data.len() + JUNK) as u32;
+        data.extend_from_slice(&[0xEE; JUNK]);
+        data.extend_from_slice(&[0x5A; CERTIFICATE as usize]);
+        PeEditor::new(&mut data)
+            .unwrap()
+            .set_data_directory(IMAGE_DIRECTORY_ENTRY_SECURITY, certificate, CERTIFICATE)
+            .unwrap();
+        data
+    }
+
+    fn unpack(data: &[u8], overlay: OverlayMode) -> Vec<u8> {
+        Unpacker {
+            overlay,
+            ..Default::default()
+        }
+        .unpack(data, &[DlfSource::CipherKey(fixture::KEY.to_vec())])
+        .unwrap()
+        .data
+    }
+
+    fn security(data: &mut [u8]) -> (u32, u32) {
+        PeEditor::new(data)
+            .unwrap()
+            .data_directory(IMAGE_DIRECTORY_ENTRY_SECURITY)
+            .unwrap()
+    }
+
+    #[test]
+    fn finds_certificate() {
+        let data = signed();
+        let overlay = find(PeFile::from_bytes(&data).unwrap(), data.len()).unwrap();
+        let offset = data.len() - JUNK - CERTIFICATE as usize;
+        assert_eq!(overlay.offset, offset);
+        assert_eq!(overlay.size, JUNK + CERTIFICATE as usize);
+        assert_eq!(
+            overlay.certificate,
+            Some(((offset + JUNK) as u32, CERTIFICATE))
+        );
+    }
+
+    #[test]
+    fn relocates_overlay() {
+        let data = signed();
+        let overlay = data.len() - JUNK - CERTIFICATE as usize;
+        let mut new = unpack(&data, OverlayMode::Relocate);
+        let end = fixture::unpacked().len();
+        assert_eq!(new.len(), end + JUNK + CERTIFICATE as usize);
+        assert_eq!(new[end..], data[overlay..]);
+        assert_eq!(security(&mut new), ((end + JUNK) as u32, CERTIFICATE));
+    }
+
+    #[test]
+    fn keeps_overlay_in_place() {
+        let mut data = signed();
+        let overlay = data.len() - JUNK - CERTIFICATE as usize;
+        let mut new = unpack(&data, OverlayMode::Keep);
+        assert_eq!(new.len(), data.len());
+        let end = fixture::unpacked().len();
+        // .ooa is gone, zeros in its place
+        assert!(new[end..overlay].iter().all(|&b| b == 0));
+        assert_eq!(new[overlay..], data[overlay..]);
+        assert_eq!(security(&mut new), security(&mut data));
+    }
+
+    #[test]
+    fn strips_overlay() {
+        let mut new = unpack(&signed(), OverlayMode::Strip);
+        assert_eq!(security(&mut new), (0, 0));
+        assert_eq!(new, fixture::unpacked());
+    }
+}
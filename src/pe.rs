@@ -0,0 +1,315 @@
+//! Editing the headers of the unpacked image
+//!
+//! Every header fix goes through [`PeEditor`], which knows where the fields of
+//! PE32 and PE32+ headers are and bounds checks every access.
+
+use pelite::image::{IMAGE_NT_OPTIONAL_HDR32_MAGIC, IMAGE_NT_OPTIONAL_HDR64_MAGIC};
+
+use crate::error::OoaError;
+
+/// `sizeof(IMAGE_SECTION_HEADER)`
+pub const SECTION_HEADER_SIZE: usize = 0x28;
+
+/// `Signature` plus `sizeof(IMAGE_FILE_HEADER)`
+const NT_FILE_HEADER_SIZE: usize = 24;
+
+// From the start of the NT headers
+const NUMBER_OF_SECTIONS: usize = 6;
+const SIZE_OF_OPTIONAL_HEADER: usize = 20;
+
+// From the start of the optional header, the same for PE32 and PE32+
+const ADDRESS_OF_ENTRY_POINT: usize = 16;
+const SECTION_ALIGNMENT: usize = 32;
+const FILE_ALIGNMENT: usize = 36;
+const SIZE_OF_IMAGE: usize = 56;
+const SIZE_OF_HEADERS: usize = 60;
+const CHECK_SUM: usize = 64;
+const NUMBER_OF_RVA_AND_SIZES32: usize = 92;
+const NUMBER_OF_RVA_AND_SIZES64: usize = 108;
+const DATA_DIRECTORY32: usize = 96;
+const DATA_DIRECTORY64: usize = 112;
+
+// From the start of a section header
+const VIRTUAL_SIZE: usize = 8;
+const VIRTUAL_ADDRESS: usize = 12;
+const SIZE_OF_RAW_DATA: usize = 16;
+const POINTER_TO_RAW_DATA: usize = 20;
+const CHARACTERISTICS: usize = 36;
+
+/// The `IMAGE_SECTION_HEADER` fields that matter for images
+#[derive(Debug, Clone)]
+pub struct SectionHeader {
+    pub name: [u8; 8],
+    pub virtual_size: u32,
+    pub virtual_address: u32,
+    pub size_of_raw_data: u32,
+    pub pointer_to_raw_data: u32,
+    pub characteristics: u32,
+}
+
+/// Typed, bounds checked access to the headers of a PE file in a byte buffer
+pub struct PeEditor<'a> {
+    data: &'a mut [u8],
+    nt_headers: usize,
+    pe32: bool,
+}
+
+impl<'a> PeEditor<'a> {
+    pub fn new(data: &'a mut [u8]) -> Result<Self, OoaError> {
+        if data.get(0..2) != Some(b"MZ") {
+            return Err(pelite::Error::BadMagic.into());
+        }
+        let nt_headers = read_u32(data, 0x3C)? as usize;
+        if data.get(nt_headers..nt_headers + 4) != Some(b"PE\0\0") {
+            return Err(pelite::Error::PeMagic.into());
+        }
+        let pe32 = match read_u16(data, nt_headers + NT_FILE_HEADER_SIZE)? {
+            IMAGE_NT_OPTIONAL_HDR32_MAGIC => true,
+            IMAGE_NT_OPTIONAL_HDR64_MAGIC => false,
+            _ => return Err(pelite::Error::PeMagic.into()),
+        };
+        Ok(PeEditor {
+            data,
+            nt_headers,
+            pe32,
+        })
+    }
+
+    pub fn is_pe32(&self) -> bool {
+        self.pe32
+    }
+
+    fn optional_header(&self) -> usize {
+        self.nt_headers + NT_FILE_HEADER_SIZE
+    }
+
+    pub fn entry_point(&self) -> Result<u32, OoaError> {
+        read_u32(self.data, self.optional_header() + ADDRESS_OF_ENTRY_POINT)
+    }
+
+    pub fn set_entry_point(&mut self, rva: u32) -> Result<(), OoaError> {
+        write_u32(
+            self.data,
+            self.optional_header() + ADDRESS_OF_ENTRY_POINT,
+            rva,
+        )
+    }
+
+    pub fn size_of_image(&self) -> Result<u32, OoaError> {
+        read_u32(self.data, self.optional_header() + SIZE_OF_IMAGE)
+    }
+
+    pub fn set_size_of_image(&mut self, size: u32) -> Result<(), OoaError> {
+        write_u32(self.data, self.optional_header() + SIZE_OF_IMAGE, size)
+    }
+
+    pub fn section_alignment(&self) -> Result<u32, OoaError> {
+        read_u32(self.data, self.optional_header() + SECTION_ALIGNMENT)
+    }
+
+    pub fn file_alignment(&self) -> Result<u32, OoaError> {
+        read_u32(self.data, self.optional_header() + FILE_ALIGNMENT)
+    }
+
+    pub fn size_of_headers(&self) -> Result<u32, OoaError> {
+        read_u32(self.data, self.optional_header() + SIZE_OF_HEADERS)
+    }
+
+    /// File offset of `CheckSum`, which [`pe_checksum`](crate::util::pe_checksum) has to skip
+    pub fn checksum_offset(&self) -> usize {
+        self.optional_header() + CHECK_SUM
+    }
+
+    pub fn set_checksum(&mut self, checksum: u32) -> Result<(), OoaError> {
+        write_u32(self.data, self.checksum_offset(), checksum)
+    }
+
+    pub fn number_of_sections(&self) -> Result<usize, OoaError> {
+        Ok(read_u16(self.data, self.nt_headers + NUMBER_OF_SECTIONS)? as usize)
+    }
+
+    pub fn set_number_of_sections(&mut self, count: u16) -> Result<(), OoaError> {
+        let off = self.nt_headers + NUMBER_OF_SECTIONS;
+        bytes_mut(self.data, off, 2)?.copy_from_slice(&count.to_le_bytes());
+        Ok(())
+    }
+
+    pub fn number_of_data_directories(&self) -> Result<usize, OoaError> {
+        let off = if self.pe32 {
+            NUMBER_OF_RVA_AND_SIZES32
+        } else {
+            NUMBER_OF_RVA_AND_SIZES64
+        };
+        Ok(read_u32(self.data, self.optional_header() + off)? as usize)
+    }
+
+    fn data_directory_offset(&self, index: usize) -> Result<usize, OoaError> {
+        if index >= self.number_of_data_directories()? {
+            return Err(pelite::Error::Bounds.into());
+        }
+        let start = if self.pe32 {
+            DATA_DIRECTORY32
+        } else {
+            DATA_DIRECTORY64
+        };
+        Ok(self.optional_header() + start + index * 8)
+    }
+
+    /// `(VirtualAddress, Size)` of data directory `index`
+    pub fn data_directory(&self, index: usize) -> Result<(u32, u32), OoaError> {
+        let off = self.data_directory_offset(index)?;
+        Ok((read_u32(self.data, off)?, read_u32(self.data, off + 4)?))
+    }
+
+    /// Returns whether the entry actually changed
+    pub fn set_data_directory(
+        &mut self,
+        index: usize,
+        va: u32,
+        size: u32,
+    ) -> Result<bool, OoaError> {
+        let off = self.data_directory_offset(index)?;
+        let changed = self.data_directory(index)? != (va, size);
+        write_u32(self.data, off, va)?;
+        write_u32(self.data, off + 4, size)?;
+        Ok(changed)
+    }
+
+    fn section_header_offset(&self, index: usize) -> Result<usize, OoaError> {
+        if index >= self.number_of_sections()? {
+            return Err(pelite::Error::Bounds.into());
+        }
+        let size_of_optional_header =
+            read_u16(self.data, self.nt_headers + SIZE_OF_OPTIONAL_HEADER)? as usize;
+        Ok(self.optional_header() + size_of_optional_header + index * SECTION_HEADER_SIZE)
+    }
+
+    pub fn section_virtual_size(&self, index: usize) -> Result<u32, OoaError> {
+        read_u32(self.data, self.section_header_offset(index)? + VIRTUAL_SIZE)
+    }
+
+    pub fn set_section_virtual_size(&mut self, index: usize, size: u32) -> Result<(), OoaError> {
+        let off = self.section_header_offset(index)? + VIRTUAL_SIZE;
+        write_u32(self.data, off, size)
+    }
+
+    pub fn section_pointer_to_raw_data(&self, index: usize) -> Result<u32, OoaError> {
+        read_u32(
+            self.data,
+            self.section_header_offset(index)? + POINTER_TO_RAW_DATA,
+        )
+    }
+
+    pub fn set_section_pointer_to_raw_data(
+        &mut self,
+        index: usize,
+        pointer: u32,
+    ) -> Result<(), OoaError> {
+        let off = self.section_header_offset(index)? + POINTER_TO_RAW_DATA;
+        write_u32(self.data, off, pointer)
+    }
+
+    /// Adds a section header after the last one
+    ///
+    /// Fails if it doesn't fit in `SizeOfHeaders`. Only touches the section table.
+    pub fn append_section_header(&mut self, header: &SectionHeader) -> Result<(), OoaError> {
+        let count = self.number_of_sections()?;
+        let size_of_optional_header =
+            read_u16(self.data, self.nt_headers + SIZE_OF_OPTIONAL_HEADER)? as usize;
+        let off = self.optional_header() + size_of_optional_header + count * SECTION_HEADER_SIZE;
+        if off + SECTION_HEADER_SIZE > self.size_of_headers()? as usize {
+            return Err(pelite::Error::Bounds.into());
+        }
+        let slot = bytes_mut(self.data, off, SECTION_HEADER_SIZE)?;
+        slot.fill(0);
+        slot[..8].copy_from_slice(&header.name);
+        write_u32(slot, VIRTUAL_SIZE, header.virtual_size)?;
+        write_u32(slot, VIRTUAL_ADDRESS, header.virtual_address)?;
+        write_u32(slot, SIZE_OF_RAW_DATA, header.size_of_raw_data)?;
+        write_u32(slot, POINTER_TO_RAW_DATA, header.pointer_to_raw_data)?;
+        write_u32(slot, CHARACTERISTICS, header.characteristics)?;
+        self.set_number_of_sections(count as u16 + 1)
+    }
+
+    /// Removes section header `index`, moving the following ones up
+    ///
+    /// Only touches the section table, raw data and `SizeOfImage` are up to the caller.
+    pub fn remove_section_header(&mut self, index: usize) -> Result<(), OoaError> {
+        let count = self.number_of_sections()?;
+        let start = self.section_header_offset(index)?;
+        let end = self.section_header_offset(count - 1)? + SECTION_HEADER_SIZE;
+        bytes_mut(self.data, start, end - start)?;
+        self.data
+            .copy_within(start + SECTION_HEADER_SIZE..end, start);
+        self.data[end - SECTION_HEADER_SIZE..end].fill(0);
+        self.set_number_of_sections(count as u16 - 1)
+    }
+}
+
+fn bytes_mut(data: &mut [u8], off: usize, len: usize) -> Result<&mut [u8], OoaError> {
+    data.get_mut(off..off + len)
+        .ok_or_else(|| pelite::Error::Bounds.into())
+}
+
+fn read_u16(data: &[u8], off: usize) -> Result<u16, OoaError> {
+    data.get(off..off + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| pelite::Error::Bounds.into())
+}
+
+fn read_u32(data: &[u8], off: usize) -> Result<u32, OoaError> {
+    data.get(off..off + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| pelite::Error::Bounds.into())
+}
+
+fn write_u32(data: &mut [u8], off: usize, value: u32) -> Result<(), OoaError> {
+    bytes_mut(data, off, 4)?.copy_from_slice(&value.to_le_bytes());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{Image, RDATA_VA, RELOC_VA};
+    use pelite::image::{IMAGE_DIRECTORY_ENTRY_IMPORT, IMAGE_DIRECTORY_ENTRY_TLS};
+    use pelite::pe32::Pe;
+    use pelite::PeFile;
+
+    #[test]
+    fn edits_pe32_headers() {
+        let mut data = Image::PE32.unpacked();
+        let mut pe = PeEditor::new(&mut data).unwrap();
+        assert!(pe.is_pe32());
+        assert_eq!(pe.entry_point().unwrap(), 0x1000);
+        assert_eq!(pe.size_of_image().unwrap(), 0x4000);
+        assert_eq!(pe.number_of_data_directories().unwrap(), 16);
+        assert_eq!(
+            pe.data_directory(IMAGE_DIRECTORY_ENTRY_IMPORT).unwrap(),
+            (RDATA_VA, 0x28)
+        );
+        assert_eq!(pe.section_pointer_to_raw_data(2).unwrap(), 0x800);
+
+        pe.set_entry_point(0x1004).unwrap();
+        pe.set_size_of_image(0x5000).unwrap();
+        pe.set_data_directory(IMAGE_DIRECTORY_ENTRY_TLS, 0x2100, 0x18)
+            .unwrap();
+        pe.set_section_virtual_size(1, 0x1000).unwrap();
+        assert!(matches!(pe.data_directory(16), Err(OoaError::Pe(_))));
+
+        // pelite reads the same fields back
+        let file = PeFile::from_bytes(&data).unwrap();
+        let PeFile::T32(file) = file else {
+            panic!("not PE32");
+        };
+        let optional = file.optional_header();
+        assert_eq!(optional.AddressOfEntryPoint, 0x1004);
+        assert_eq!(optional.SizeOfImage, 0x5000);
+        assert_eq!(optional.ImageBase, 0x40_0000);
+        let tls = &file.data_directory()[IMAGE_DIRECTORY_ENTRY_TLS];
+        assert_eq!((tls.VirtualAddress, tls.Size), (0x2100, 0x18));
+        let sections = file.section_headers().as_slice();
+        assert_eq!(sections[1].VirtualSize, 0x1000);
+        assert_eq!(sections[2].VirtualAddress, RELOC_VA);
+    }
+}
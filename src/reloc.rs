@@ -0,0 +1,153 @@
+//! Original base relocations kept in `.ooa`
+
+use pelite::PeFile;
+
+use crate::ooa::Section;
+use crate::util::patch_rva;
+
+pub const IMAGE_REL_BASED_ABSOLUTE: u8 = 0;
+pub const IMAGE_REL_BASED_HIGHLOW: u8 = 3;
+pub const IMAGE_REL_BASED_DIR64: u8 = 10;
+
+/// One `IMAGE_BASE_RELOCATION` block
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RelocBlock {
+    pub page_rva: u32,
+    /// Raw entries, type in the top 4 bits and page offset in the low 12
+    pub entries: Vec<u16>,
+}
+
+impl RelocBlock {
+    pub fn size(&self) -> usize {
+        8 + self.entries.len() * 2
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.size());
+        bytes.extend_from_slice(&self.page_rva.to_le_bytes());
+        bytes.extend_from_slice(&(self.size() as u32).to_le_bytes());
+        for entry in &self.entries {
+            bytes.extend_from_slice(&entry.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+/// Decodes blocks until the data runs out, a zero block or something that isn't a block
+pub fn decode(data: &[u8]) -> Vec<RelocBlock> {
+    let mut blocks = Vec::new();
+    let mut rest = data;
+    while rest.len() >= 8 {
+        let page_rva = u32::from_le_bytes([rest[0], rest[1], rest[2], rest[3]]);
+        let size = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+        if size < 8 || !size.is_multiple_of(2) || size > rest.len() {
+            break;
+        }
+        let entries = rest[8..size]
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        blocks.push(RelocBlock { page_rva, entries });
+        rest = &rest[size..];
+    }
+    blocks
+}
+
+pub fn encode(blocks: &[RelocBlock]) -> Vec<u8> {
+    blocks.iter().flat_map(RelocBlock::to_bytes).collect()
+}
+
+/// Sanity checks `blocks` against an image of `size_of_image`, returning the problems found
+pub fn validate(blocks: &[RelocBlock], size_of_image: u32) -> Vec<String> {
+    let mut problems = Vec::new();
+    for block in blocks {
+        if !block.page_rva.is_multiple_of(0x1000) {
+            problems.push(format!("Block {:08X} isn't page aligned", block.page_rva));
+        }
+        if block.page_rva >= size_of_image {
+            problems.push(format!("Block {:08X} is outside the image", block.page_rva));
+        }
+        if let Some(entry) = block.entries.iter().find(|&&e| {
+            !matches!(
+                (e >> 12) as u8,
+                IMAGE_REL_BASED_ABSOLUTE | IMAGE_REL_BASED_HIGHLOW | IMAGE_REL_BASED_DIR64
+            )
+        }) {
+            problems.push(format!(
+                "Block {:08X} has an unexpected relocation type {}",
+                block.page_rva,
+                entry >> 12
+            ));
+        }
+    }
+    problems
+}
+
+/// Writes the relocations from `.ooa` to `reloc_dir` if they're valid and what's there differs
+///
+/// Returns what was done.
+pub fn restore(file: PeFile, new: &mut [u8], section: &Section, size_of_image: u32) -> Vec<String> {
+    let mut notes = Vec::new();
+    if section.relocs.is_empty() || section.reloc_dir.va == 0 {
+        return notes;
+    }
+    let problems = validate(&section.relocs, size_of_image);
+    if !problems.is_empty() {
+        notes.push(format!(
+            "Not restoring relocations from .ooa: {}",
+            problems.join(", ")
+        ));
+        return notes;
+    }
+    let bytes = encode(&section.relocs);
+    if bytes.len() != section.reloc_dir.size as usize {
+        notes.push(format!(
+            "Not restoring relocations from .ooa: {:#X} bytes don't match RelocDir ({:#X} bytes)",
+            bytes.len(),
+            section.reloc_dir.size
+        ));
+        return notes;
+    }
+    match patch_rva(file, new, section.reloc_dir.va, &bytes) {
+        Ok(true) => notes.push(format!(
+            "Restored {} relocation block(s)",
+            section.relocs.len()
+        )),
+        Ok(false) => {}
+        Err(err) => notes.push(format!("Failed to restore relocations: {}", err)),
+    }
+    notes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_encode() {
+        let blocks = vec![
+            RelocBlock {
+                page_rva: 0x1000,
+                entries: vec![0xA010, 0xA018],
+            },
+            RelocBlock {
+                page_rva: 0x2000,
+                entries: vec![0xA000, 0],
+            },
+        ];
+        let mut data = encode(&blocks);
+        // Zero block ends the list
+        data.extend_from_slice(&[0u8; 8]);
+        assert_eq!(decode(&data), blocks);
+        assert!(validate(&blocks, 0x3000).is_empty());
+    }
+
+    #[test]
+    fn validate_reports_problems() {
+        let blocks = [RelocBlock {
+            page_rva: 0x1234,
+            entries: vec![0x5000],
+        }];
+        assert_eq!(validate(&blocks, 0x1000).len(), 3);
+    }
+}
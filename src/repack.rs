@@ -0,0 +1,122 @@
+//! The inverse of [`unpack`](crate::unpack)
+//!
+//! Encrypts the sections listed in `Section::enc_blocks` and appends a `.ooa`
+//! section built by a [`Layout`], so round trips can be tested without real
+//! game binaries.
+
+use pelite::image::{IMAGE_SCN_CNT_INITIALIZED_DATA, IMAGE_SCN_MEM_READ};
+use pelite::PeFile;
+
+use crate::checksum;
+use crate::error::OoaError;
+use crate::layout::Layout;
+use crate::ooa::Section;
+use crate::pe::{PeEditor, SectionHeader};
+use crate::util::{aes_encrypt, image_base, size_of_image};
+
+fn align_up(value: u32, align: u32) -> Result<u32, OoaError> {
+    if align == 0 {
+        Ok(value)
+    } else {
+        value
+            .div_ceil(align)
+            .checked_mul(align)
+            .ok_or_else(|| OoaError::Repack("image doesn't fit in 4 GiB".to_owned()))
+    }
+}
+
+/// Re-encrypts the unpacked image in `pe_bytes` into `.ooa` form
+///
+/// Only the `va` of each `EncBlock` in `section` matters, sizes and checksums are
+/// filled in. `image_base` and `size_of_image` are taken from the image when zero.
+/// Protected sections have to end in 16 zero bytes, which is where the padding
+/// block goes. Any overlay is dropped.
+pub fn repack(
+    pe_bytes: &[u8],
+    layout: &Layout,
+    section: &Section,
+    key: &[u8],
+) -> Result<Vec<u8>, OoaError> {
+    let file = PeFile::from_bytes(pe_bytes)?;
+    let mut section = section.clone();
+    if section.image_base == 0 {
+        section.image_base = image_base(file);
+    }
+    if section.size_of_image == 0 {
+        section.size_of_image = size_of_image(file);
+    }
+
+    let headers = file.section_headers().as_slice();
+    let last = headers.last().ok_or(OoaError::NoSections)?;
+    let raw_end = headers
+        .iter()
+        .map(|s| s.PointerToRawData.saturating_add(s.SizeOfRawData) as usize)
+        .max()
+        .unwrap_or(0);
+    let mut new = pe_bytes.get(..raw_end).ok_or(OoaError::Truncated)?.to_vec();
+
+    // The unpacker takes every IV from the buffer as it goes, encrypting backwards
+    // leaves exactly what it will find there
+    for block in section.enc_blocks.iter_mut().rev() {
+        let header = headers
+            .iter()
+            .find(|s| s.VirtualAddress == block.va)
+            .ok_or(OoaError::SectionLookup(block.va))?;
+        let start = header.PointerToRawData as usize;
+        let end = header.PointerToRawData.saturating_add(header.SizeOfRawData) as usize;
+        if start < 0x10 || end < start + 0x10 || !(end - start).is_multiple_of(0x10) {
+            return Err(OoaError::Repack(format!(
+                "section at {:08X} can't be encrypted in place",
+                block.va
+            )));
+        }
+        if new[end - 0x10..end] != [0u8; 16] {
+            return Err(OoaError::Repack(format!(
+                "section at {:08X} doesn't end in 16 zero bytes",
+                block.va
+            )));
+        }
+        block.raw_size = header.SizeOfRawData;
+        block.virtual_size = header.VirtualSize;
+        block.crc = checksum::crc32(&new[start..end]);
+        let mut iv = [0u8; 16];
+        iv.copy_from_slice(&new[start - 0x10..start]);
+        let encrypted = aes_encrypt(key, &iv, &new[start..end - 0x10])?;
+        new[start..end].copy_from_slice(&encrypted);
+        block.crc2 = checksum::crc32(&new[start..end]);
+    }
+
+    let mut ooa = layout.serialize(&section)?;
+    let mut pe = PeEditor::new(&mut new)?;
+    let section_alignment = pe.section_alignment()?;
+    let file_alignment = pe.file_alignment()?;
+    let virtual_size = ooa.len() as u32;
+    ooa.resize(align_up(virtual_size, file_alignment)? as usize, 0);
+    let last_size = if last.VirtualSize != 0 {
+        last.VirtualSize
+    } else {
+        last.SizeOfRawData
+    };
+    let virtual_address = align_up(
+        last.VirtualAddress.saturating_add(last_size),
+        section_alignment,
+    )?;
+    let pointer_to_raw_data = align_up(raw_end as u32, file_alignment)?;
+    pe.append_section_header(&SectionHeader {
+        name: *b".ooa\0\0\0\0",
+        virtual_size,
+        virtual_address,
+        size_of_raw_data: ooa.len() as u32,
+        pointer_to_raw_data,
+        characteristics: IMAGE_SCN_CNT_INITIALIZED_DATA | IMAGE_SCN_MEM_READ,
+    })
+    .map_err(|_| OoaError::Repack("no room for another section header".to_owned()))?;
+    pe.set_size_of_image(align_up(
+        virtual_address.saturating_add(virtual_size),
+        section_alignment,
+    )?)?;
+
+    new.resize(pointer_to_raw_data as usize, 0);
+    new.extend_from_slice(&ooa);
+    Ok(new)
+}
@@ -0,0 +1,146 @@
+//! Restoring the TLS directory and callbacks kept in `.ooa`
+//!
+//! The protector points TLS at its own stub, the original directory RVA and
+//! callbacks are stored in `.ooa`.
+
+use std::ops::Range;
+
+use pelite::image::IMAGE_DIRECTORY_ENTRY_TLS;
+use pelite::PeFile;
+
+use crate::ooa::Section;
+use crate::pe::PeEditor;
+use crate::util::{image_base, is_pe32, patch_rva, pointer_size};
+
+/// `sizeof(IMAGE_TLS_DIRECTORY64)`
+pub const TLS_DIRECTORY_SIZE: u32 = 0x28;
+/// `sizeof(IMAGE_TLS_DIRECTORY32)`
+pub const TLS_DIRECTORY32_SIZE: u32 = 0x18;
+/// Offset of `AddressOfCallBacks` in `IMAGE_TLS_DIRECTORY64`
+const ADDRESS_OF_CALLBACKS: u32 = 0x18;
+/// Offset of `AddressOfCallBacks` in `IMAGE_TLS_DIRECTORY32`
+const ADDRESS_OF_CALLBACKS32: u32 = 0x0C;
+
+/// Every TLS callback VA kept in `.ooa`, in order
+pub fn callbacks(section: &Section) -> Vec<u64> {
+    std::iter::once(section.tls_first_callback)
+        .filter(|&va| va != 0)
+        .chain(section.tls_callbacks.iter().copied())
+        .collect()
+}
+
+/// Writes the TLS directory entry and callback array back into the image
+///
+/// `ooa` is the RVA range of the `.ooa` section. Returns what was done.
+pub fn restore(file: PeFile, new: &mut [u8], section: &Section, ooa: Range<u32>) -> Vec<String> {
+    let mut notes = Vec::new();
+    if section.tls == 0 {
+        return notes;
+    }
+    let image_base = image_base(file);
+    let width = pointer_size(file);
+    let (directory_size, address_of_callbacks_off) = if is_pe32(file) {
+        (TLS_DIRECTORY32_SIZE, ADDRESS_OF_CALLBACKS32)
+    } else {
+        (TLS_DIRECTORY_SIZE, ADDRESS_OF_CALLBACKS)
+    };
+
+    match PeEditor::new(new).and_then(|mut pe| {
+        pe.set_data_directory(IMAGE_DIRECTORY_ENTRY_TLS, section.tls, directory_size)
+    }) {
+        Ok(true) => notes.push(format!("Restored TLS directory: {:08X}", section.tls)),
+        Ok(false) => {}
+        Err(err) => notes.push(format!("Failed to restore TLS directory: {}", err)),
+    }
+
+    if section.tls_callback != 0 {
+        let address_of_callbacks = image_base.wrapping_add(section.tls_callback as u64);
+        match patch_rva(
+            file,
+            new,
+            section.tls.saturating_add(address_of_callbacks_off),
+            &address_of_callbacks.to_le_bytes()[..width],
+        ) {
+            Ok(true) => notes.push("Restored TLS AddressOfCallBacks".to_owned()),
+            Ok(false) => {}
+            Err(err) => notes.push(format!("Failed to restore TLS AddressOfCallBacks: {}", err)),
+        }
+
+        // Without the list only the first entry is known, the rest of the array is left alone
+        let callbacks = if section.tls_callback_list {
+            callbacks(section)
+                .into_iter()
+                .chain(std::iter::once(0))
+                .collect::<Vec<_>>()
+        } else {
+            vec![section.tls_first_callback]
+        };
+        let array = callbacks
+            .iter()
+            .flat_map(|va| va.to_le_bytes().into_iter().take(width))
+            .collect::<Vec<_>>();
+        match patch_rva(file, new, section.tls_callback, &array) {
+            Ok(true) if section.tls_callback_list => {
+                notes.push(format!("Restored {} TLS callback(s)", callbacks.len() - 1))
+            }
+            Ok(true) => notes.push("Restored the first TLS callback".to_owned()),
+            Ok(false) => {}
+            Err(err) => notes.push(format!("Failed to restore TLS callbacks: {}", err)),
+        }
+    }
+
+    for va in callbacks(section) {
+        let rva = va.wrapping_sub(image_base);
+        if rva <= u32::MAX as u64 && ooa.contains(&(rva as u32)) {
+            notes.push(format!("TLS callback {:016X} points into .ooa", va));
+        }
+    }
+    notes
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::Image;
+    use crate::layout::LAYOUTS;
+    use crate::{unpack, DlfSource, UnpackedImage};
+
+    fn unpack_tls(image: &Image, index: usize) -> UnpackedImage {
+        let unpacked = unpack(
+            &image.protected_tls(&LAYOUTS[index]),
+            &[DlfSource::CipherKey(crate::fixture::KEY.to_vec())],
+        )
+        .unwrap();
+        assert_eq!(unpacked.data, image.unpacked_tls());
+        unpacked
+    }
+
+    #[test]
+    fn restores_callback_list() {
+        // BF2042
+        let unpacked = unpack_tls(&Image::DEFAULT, 3);
+        assert!(unpacked.section.tls_callback_list);
+        assert!(unpacked
+            .notes
+            .contains(&"Restored TLS directory: 00002100".to_owned()));
+        assert!(unpacked
+            .notes
+            .contains(&"Restored 2 TLS callback(s)".to_owned()));
+    }
+
+    #[test]
+    fn keeps_callbacks_it_doesnt_know() {
+        // Retail Apex, only the first callback is kept and the second one has to survive
+        let unpacked = unpack_tls(&Image::DEFAULT, 1);
+        assert!(!unpacked.section.tls_callback_list);
+        assert!(unpacked
+            .notes
+            .contains(&"Restored the first TLS callback".to_owned()));
+    }
+
+    #[test]
+    fn restores_pe32_tls() {
+        for index in [1, 3] {
+            unpack_tls(&Image::PE32, index);
+        }
+    }
+}
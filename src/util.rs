@@ -1,80 +1,279 @@
+use std::path::{Path, PathBuf};
+
 use aes::Aes128;
-use block_modes::block_padding::Pkcs7;
+use block_modes::block_padding::{NoPadding, Pkcs7};
 use block_modes::{BlockMode, Cbc};
 
+use pelite::{PeFile, Wrap};
+
+use crate::error::OoaError;
+
 type Aes128Cbc = Cbc<Aes128, Pkcs7>;
+type Aes128CbcRaw = Cbc<Aes128, NoPadding>;
 
 const DLF_KEY: [u8; 16] = [
     65, 50, 114, 45, 208, 130, 239, 176, 220, 100, 87, 197, 118, 104, 202, 9,
 ];
 const IV: [u8; 16] = [0u8; 16];
+/// Size of the header some DLFs have in front of the encrypted data
+const DLF_HEADER_SIZE: usize = 0x41;
 const CIPHER_TAG: &str = "<CipherKey>";
 const BASE64_16_LEN: usize = 24;
 
 // Apex has weird behaviour when 0x1000-0x10 isn't full zeroes...
 pub fn aes_decrypt(key: &[u8], iv: &[u8], enc: &[u8]) -> Option<Vec<u8>> {
-    let cipher = Aes128Cbc::new_from_slices(key, iv).unwrap();
-    if let Ok(data) = cipher.decrypt_vec(enc) {
-        Some(data)
+    let cipher = Aes128Cbc::new_from_slices(key, iv).ok()?;
+    cipher.decrypt_vec(enc).ok()
+}
+
+/// PKCS#7 padded, the output is one block longer when `plain` is block aligned
+pub fn aes_encrypt(key: &[u8], iv: &[u8], plain: &[u8]) -> Result<Vec<u8>, OoaError> {
+    let cipher = Aes128Cbc::new_from_slices(key, iv).map_err(|_| OoaError::KeyLength(key.len()))?;
+    Ok(cipher.encrypt_vec(plain))
+}
+
+/// Decrypts in place, leaving judging the padding to the caller
+pub fn aes_decrypt_inplace_raw(key: &[u8], iv: &[u8], buf: &mut [u8]) -> Result<(), OoaError> {
+    let cipher =
+        Aes128CbcRaw::new_from_slices(key, iv).map_err(|_| OoaError::KeyLength(key.len()))?;
+    cipher.decrypt(buf)?;
+    Ok(())
+}
+
+/// Standard PE image checksum, as `CheckSumMappedFile` computes it
+///
+/// `checksum_off` is the file offset of the optional header `CheckSum` field, which is skipped.
+pub fn pe_checksum(data: &[u8], checksum_off: usize) -> u32 {
+    let mut sum = 0u64;
+    for (i, chunk) in data.chunks(2).enumerate() {
+        let off = i * 2;
+        if off >= checksum_off && off < checksum_off + 4 {
+            continue;
+        }
+        let word = if chunk.len() == 2 {
+            u16::from_le_bytes([chunk[0], chunk[1]])
+        } else {
+            chunk[0] as u16
+        };
+        sum += word as u64;
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    sum = (sum & 0xFFFF) + (sum >> 16);
+    (sum as u32).wrapping_add(data.len() as u32)
+}
+
+/// Whether `file` is a 32 bit (PE32) image
+pub fn is_pe32(file: PeFile) -> bool {
+    matches!(file, Wrap::T32(_))
+}
+
+/// Size of a pointer (and a thunk) in the image
+pub fn pointer_size(file: PeFile) -> usize {
+    if is_pe32(file) {
+        4
     } else {
-        None
+        8
     }
 }
 
-pub fn aes_decrypt_inplace(key: &[u8], iv: &[u8], buf: &mut [u8]) {
-    let cipher = Aes128Cbc::new_from_slices(key, iv).unwrap();
-    cipher.decrypt(buf).unwrap();
+/// `ImageBase`, widened for PE32
+pub fn image_base(file: PeFile) -> u64 {
+    match file.optional_header() {
+        Wrap::T32(header) => header.ImageBase as u64,
+        Wrap::T64(header) => header.ImageBase,
+    }
 }
 
-pub fn decrypt_dlf(data: &[u8]) -> Vec<u8> {
-    if let Some(data) = aes_decrypt(&DLF_KEY, &IV, &data[0x41..]) {
-        data
+pub fn size_of_image(file: PeFile) -> u32 {
+    match file.optional_header() {
+        Wrap::T32(header) => header.SizeOfImage,
+        Wrap::T64(header) => header.SizeOfImage,
+    }
+}
+
+pub fn rva_to_file_offset(file: PeFile, rva: u32) -> pelite::Result<usize> {
+    match file {
+        Wrap::T32(file) => pelite::pe32::Pe::rva_to_file_offset(file, rva),
+        Wrap::T64(file) => pelite::pe64::Pe::rva_to_file_offset(file, rva),
+    }
+}
+
+/// Writes `bytes` at `rva` of the file image in `new`, laid out like `file`
+///
+/// Returns whether anything actually changed.
+pub fn patch_rva(file: PeFile, new: &mut [u8], rva: u32, bytes: &[u8]) -> Result<bool, OoaError> {
+    let offset = rva_to_file_offset(file, rva)?;
+    // The whole range has to be backed by raw data of the same section
+    let end_offset = rva_to_file_offset(
+        file,
+        rva.saturating_add(bytes.len().saturating_sub(1) as u32),
+    )?;
+    if end_offset != offset + bytes.len() - 1 || end_offset >= new.len() {
+        return Err(OoaError::Truncated);
+    }
+    let target = &mut new[offset..offset + bytes.len()];
+    if target == bytes {
+        Ok(false)
     } else {
-        aes_decrypt(&DLF_KEY, &IV, data).expect("Failed DLF decrypt!")
+        target.copy_from_slice(bytes);
+        Ok(true)
     }
 }
 
-// Good version is Windows only yeah...
-// btw if anyone knows how to unretardaise this - lemme know plox
-pub fn get_dlf_auto(content_id: &str) -> Option<Vec<u8>> {
-    #[cfg(target_os = "windows")]
+/// How an encrypted DLF is laid out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DlfLayout {
+    /// `0x41` byte header, then the encrypted license
+    Header,
+    /// Just the encrypted license
+    Headerless,
+}
+
+/// Inverse of [`decrypt_dlf`]
+///
+/// The header's contents are unknown and ignored when decrypting, it's written as zeros.
+pub fn encrypt_dlf(dlf: &[u8], layout: DlfLayout) -> Vec<u8> {
+    let mut data = match layout {
+        DlfLayout::Header => vec![0u8; DLF_HEADER_SIZE],
+        DlfLayout::Headerless => Vec::new(),
+    };
+    // Key and IV are fixed sizes, this can't fail
+    data.extend(aes_encrypt(&DLF_KEY, &IV, dlf).expect("DLF key"));
+    data
+}
+
+pub fn decrypt_dlf(data: &[u8]) -> Result<Vec<u8>, OoaError> {
+    if let Some(data) = data
+        .get(DLF_HEADER_SIZE..)
+        .and_then(|d| aes_decrypt(&DLF_KEY, &IV, d))
     {
-        let path = if let Ok(val) = std::env::var("ProgramData") {
-            format!("{}\\Electronic Arts\\EA Services\\License\\", val)
-        } else {
-            "C:\\ProgramData\\Electronic Arts\\EA Services\\License\\".to_owned()
-        };
-        if let Ok(data) = std::fs::read(path.clone() + content_id + ".dlf") {
-            Some(decrypt_dlf(&data))
-        } else if let Ok(data) = std::fs::read(path + content_id + "_cached.dlf") {
-            Some(decrypt_dlf(&data))
-        } else if let Ok(data) = std::fs::read(content_id.to_owned() + ".dlf") {
-            Some(decrypt_dlf(&data))
-        } else if let Ok(data) = std::fs::read(content_id.to_owned() + "_cached.dlf") {
-            Some(decrypt_dlf(&data))
-        } else {
-            None
+        Ok(data)
+    } else {
+        aes_decrypt(&DLF_KEY, &IV, data).ok_or(OoaError::BadDlf)
+    }
+}
+
+/// Where the EA app keeps licenses, relative to a Windows (or Wine) `C:\`
+const LICENSE_DIR: &str = "ProgramData/Electronic Arts/EA Services/License";
+
+/// Every Wine prefix under `dir` that looks like one
+fn prefixes_in(dir: &Path, subdir: &str) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut prefixes = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().join(subdir))
+        .filter(|path| path.join("drive_c").is_dir())
+        .collect::<Vec<_>>();
+    prefixes.sort();
+    prefixes
+}
+
+/// License directories of the Wine prefixes we know to look for
+///
+/// `$WINEPREFIX`, `~/.wine`, Steam (Proton) `compatdata/*/pfx`, Lutris' `~/Games/*`
+/// and Bottles, native and Flatpak. Only existing directories are returned.
+pub fn wine_license_dirs(home: Option<&Path>, wineprefix: Option<&Path>) -> Vec<PathBuf> {
+    let mut prefixes = Vec::new();
+    prefixes.extend(wineprefix.map(Path::to_path_buf));
+    if let Some(home) = home {
+        prefixes.push(home.join(".wine"));
+        for steam in [
+            ".steam/steam",
+            ".local/share/Steam",
+            ".var/app/com.valvesoftware.Steam/.local/share/Steam",
+        ] {
+            prefixes.extend(prefixes_in(
+                &home.join(steam).join("steamapps/compatdata"),
+                "pfx",
+            ));
+        }
+        prefixes.extend(prefixes_in(&home.join("Games"), ""));
+        for bottles in [
+            ".local/share/bottles/bottles",
+            ".var/app/com.usebottles.bottles/data/bottles/bottles",
+        ] {
+            prefixes.extend(prefixes_in(&home.join(bottles), ""));
+        }
+    }
+    let mut dirs = Vec::new();
+    for prefix in prefixes {
+        let dir = prefix.join("drive_c").join(LICENSE_DIR);
+        if dir.is_dir() && !dirs.contains(&dir) {
+            dirs.push(dir);
         }
     }
+    dirs
+}
+
+/// Directories [`get_dlf_auto`] looks in, in order
+pub fn dlf_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::new();
+    #[cfg(target_os = "windows")]
+    dirs.push(
+        Path::new(&std::env::var("ProgramData").unwrap_or_else(|_| "C:\\ProgramData".to_owned()))
+            .join("Electronic Arts\\EA Services\\License"),
+    );
     #[cfg(not(target_os = "windows"))]
-    {
-        if let Ok(data) = std::fs::read(content_id.to_owned() + ".dlf") {
-            Some(decrypt_dlf(&data))
-        } else if let Ok(data) = std::fs::read(content_id.to_owned() + "_cached.dlf") {
-            Some(decrypt_dlf(&data))
-        } else {
-            None
+    dirs.extend(wine_license_dirs(
+        std::env::var_os("HOME").as_deref().map(Path::new),
+        std::env::var_os("WINEPREFIX").as_deref().map(Path::new),
+    ));
+    dirs.push(PathBuf::new());
+    dirs
+}
+
+/// Looks for `<content_id>.dlf` or `<content_id>_cached.dlf` in `dir`
+///
+/// Returns the path that was used along with the decrypted license. Files that
+/// don't decrypt are skipped, which is noted in `notes`.
+pub fn find_dlf_in(
+    dir: &Path,
+    content_id: &str,
+    notes: &mut Vec<String>,
+) -> Option<(PathBuf, Vec<u8>)> {
+    for name in [
+        format!("{}.dlf", content_id),
+        format!("{}_cached.dlf", content_id),
+    ] {
+        let path = dir.join(name);
+        if let Ok(data) = std::fs::read(&path) {
+            match decrypt_dlf(&data) {
+                Ok(dlf) => return Some((path, dlf)),
+                Err(err) => notes.push(format!("Skipping {}: {}", path.display(), err)),
+            }
         }
     }
+    None
 }
 
+/// [`find_dlf_in`] every one of [`dlf_dirs`]
+pub fn get_dlf_auto(content_id: &str, notes: &mut Vec<String>) -> Option<(PathBuf, Vec<u8>)> {
+    dlf_dirs()
+        .iter()
+        .find_map(|dir| find_dlf_in(dir, content_id, notes))
+}
+
+/// Saves a decrypted license as `<store>/<content_id>.dlf`, so [`find_dlf_in`] finds it
+///
+/// Written with the header layout, whatever the original one was.
+pub fn store_dlf(store: &Path, content_id: &str, dlf: &[u8]) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(store)?;
+    let path = store.join(format!("{}.dlf", content_id));
+    std::fs::write(&path, encrypt_dlf(dlf, DlfLayout::Header))?;
+    Ok(path)
+}
+
+/// Quick CipherKey lookup that doesn't need the DLF to be well formed, see
+/// [`License::parse`](crate::license::License::parse) for the real thing
 pub fn dlf_get_cipher(dlf: &[u8]) -> Option<Vec<u8>> {
     let string = String::from_utf8_lossy(dlf);
     if let Some(pos) = string.find(CIPHER_TAG) {
         let pos = pos + CIPHER_TAG.len();
         // let string = &string[pos..pos + string[pos..].find('<').unwrap_or(string.len() - pos)];
         if let Ok(mut data) = base64::decode_config(
-            &string[pos..pos + BASE64_16_LEN],
+            string.get(pos..pos + BASE64_16_LEN)?,
             base64::STANDARD.decode_allow_trailing_bits(true),
         ) {
             data.truncate(16);
@@ -86,3 +285,56 @@ pub fn dlf_get_cipher(dlf: &[u8]) -> Option<Vec<u8>> {
         None
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture;
+
+    #[test]
+    fn dlf_round_trips() {
+        for layout in [DlfLayout::Header, DlfLayout::Headerless] {
+            let encrypted = encrypt_dlf(&fixture::dlf_plain(), layout);
+            let dlf = decrypt_dlf(&encrypted).unwrap();
+            assert_eq!(dlf, fixture::dlf_plain(), "{:?}", layout);
+            assert_eq!(dlf_get_cipher(&dlf).unwrap(), fixture::KEY);
+        }
+    }
+
+    #[test]
+    fn finds_wine_prefixes() {
+        let home = std::env::temp_dir().join(format!("rse_ooa_decrypt-{}", std::process::id()));
+        let proton = home.join(".steam/steam/steamapps/compatdata/1237970/pfx");
+        let bottle = home.join(".local/share/bottles/bottles/EA");
+        for prefix in [&proton, &bottle] {
+            std::fs::create_dir_all(prefix.join("drive_c").join(LICENSE_DIR)).unwrap();
+        }
+        // Not a prefix
+        std::fs::create_dir_all(home.join("Games/notes")).unwrap();
+
+        let dirs = wine_license_dirs(Some(&home), None);
+        std::fs::remove_dir_all(&home).unwrap();
+        assert_eq!(
+            dirs,
+            [
+                proton.join("drive_c").join(LICENSE_DIR),
+                bottle.join("drive_c").join(LICENSE_DIR)
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_garbage_dlf() {
+        assert!(matches!(decrypt_dlf(&[0x42; 0x50]), Err(OoaError::BadDlf)));
+    }
+
+    #[test]
+    fn checksum_known_answer() {
+        // 0xFFFF + 0x0002 carries into 0x0002, the field is skipped, the odd byte
+        // adds 0x01 and the length 9: 0x0C
+        let data = [0xFF, 0xFF, 0x02, 0x00, 0xAB, 0xCD, 0xEF, 0x12, 0x01];
+        assert_eq!(pe_checksum(&data, 4), 0x0000_000C);
+        // Without skipping: 0x0002 + 0xCDAB + 0x12EF + 0x0001 is 0xE09D, plus 9
+        assert_eq!(pe_checksum(&data, data.len()), 0x0000_E0A6);
+    }
+}
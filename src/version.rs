@@ -0,0 +1,170 @@
+use crate::error::OoaError;
+use crate::get_ooa_hash;
+use crate::layout::LAYOUTS;
+use crate::ooa::Section;
+
+/// Parser for one `.ooa` build, identified by `SHA1(version)` stored at `0x2A`
+pub trait VersionParser: Send + Sync {
+    /// `SHA1` of [`VersionParser::version`]
+    fn hash(&self) -> [u8; 20];
+    /// Version string, e.g. `5.02.04.66`
+    fn version(&self) -> &str;
+    /// Games known to ship this version
+    fn name(&self) -> &str {
+        self.version()
+    }
+    /// Parses `.ooa` section contents
+    fn parse(&self, data: &[u8]) -> Result<Section, OoaError>;
+}
+
+/// [`VersionParser`] backed by a plain function
+pub struct FnParser {
+    pub hash: [u8; 20],
+    pub version: &'static str,
+    pub name: &'static str,
+    pub parse: fn(&[u8]) -> Result<Section, OoaError>,
+}
+
+impl VersionParser for FnParser {
+    fn hash(&self) -> [u8; 20] {
+        self.hash
+    }
+
+    fn version(&self) -> &str {
+        self.version
+    }
+
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn parse(&self, data: &[u8]) -> Result<Section, OoaError> {
+        (self.parse)(data)
+    }
+}
+
+/// Set of known `.ooa` versions
+pub struct Registry {
+    parsers: Vec<Box<dyn VersionParser>>,
+}
+
+impl Registry {
+    /// Registry without any parsers, see [`Registry::default`] for the built-in ones
+    pub fn empty() -> Self {
+        Registry {
+            parsers: Vec::new(),
+        }
+    }
+
+    /// Adds a parser, replacing any already registered one with the same hash
+    pub fn register(&mut self, parser: Box<dyn VersionParser>) {
+        let hash = parser.hash();
+        self.parsers.retain(|p| p.hash() != hash);
+        self.parsers.push(parser);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &dyn VersionParser> {
+        self.parsers.iter().map(|p| p.as_ref())
+    }
+
+    pub fn by_hash(&self, hash: &[u8; 20]) -> Option<&dyn VersionParser> {
+        self.iter().find(|p| &p.hash() == hash)
+    }
+
+    pub fn by_version(&self, version: &str) -> Option<&dyn VersionParser> {
+        self.iter().find(|p| p.version() == version)
+    }
+
+    /// Picks the parser by the hash at `0x2A` and runs it
+    pub fn parse(&self, data: &[u8]) -> Result<(&dyn VersionParser, Section), OoaError> {
+        let hash = get_ooa_hash(data).ok_or(OoaError::Truncated)?;
+        let parser = self.by_hash(&hash).ok_or(OoaError::UnknownVersion(hash))?;
+        Ok((parser, parser.parse(data)?))
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Registry::empty();
+        for layout in LAYOUTS {
+            registry.register(Box::new(layout));
+        }
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crack::version_hash;
+    use crate::{fixture, DlfSource, Unpacker};
+
+    const CUSTOM_VERSION: &str = "9.99.99.99";
+
+    /// A build that's laid out like Apex, but with its own hash
+    fn parse_custom(data: &[u8]) -> Result<Section, OoaError> {
+        let mut data = data.to_vec();
+        let hash = data.get_mut(0x2A..0x3E).ok_or(OoaError::Truncated)?;
+        hash.copy_from_slice(&LAYOUTS[1].hash);
+        LAYOUTS[1].parse(&data)
+    }
+
+    fn custom_parser(name: &'static str) -> Box<dyn VersionParser> {
+        Box::new(FnParser {
+            hash: version_hash(CUSTOM_VERSION),
+            version: CUSTOM_VERSION,
+            name,
+            parse: parse_custom,
+        })
+    }
+
+    #[test]
+    fn picks_registered_parser() {
+        let mut protected = fixture::protected(&LAYOUTS[1]);
+        let pos = protected
+            .windows(20)
+            .position(|w| w == LAYOUTS[1].hash)
+            .unwrap();
+        protected[pos..pos + 20].copy_from_slice(&version_hash(CUSTOM_VERSION));
+
+        let mut registry = Registry::default();
+        registry.register(custom_parser("Custom"));
+        let unpacked = Unpacker {
+            registry,
+            heuristic: false,
+            ..Default::default()
+        }
+        .unpack(&protected, &[DlfSource::CipherKey(fixture::KEY.to_vec())])
+        .unwrap();
+        assert_eq!(unpacked.version, CUSTOM_VERSION);
+        assert_eq!(unpacked.confidence, None);
+        assert_eq!(unpacked.data, fixture::unpacked());
+    }
+
+    #[test]
+    fn register_replaces_same_hash() {
+        let mut registry = Registry::default();
+        let builtin = registry.iter().count();
+        registry.register(custom_parser("Custom"));
+        registry.register(custom_parser("Custom, again"));
+        assert_eq!(registry.iter().count(), builtin + 1);
+        assert_eq!(
+            registry
+                .by_hash(&version_hash(CUSTOM_VERSION))
+                .unwrap()
+                .name(),
+            "Custom, again"
+        );
+    }
+
+    #[test]
+    fn looks_up_by_version() {
+        let registry = Registry::default();
+        for layout in &LAYOUTS {
+            let parser = registry.by_version(layout.version).unwrap();
+            assert_eq!(parser.hash(), layout.hash);
+            assert_eq!(parser.name(), layout.name);
+        }
+        assert!(registry.by_version(CUSTOM_VERSION).is_none());
+    }
+}